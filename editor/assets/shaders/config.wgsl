@@ -3,7 +3,6 @@ const COLOR_MULTIPLIER: vec4<f32> = vec4<f32>(0.0, 0.2, .0, 1.0);
 struct FernResult {
     pos: vec3<f32>,
     normal: vec3<f32>,
-    ao: f32,
     uv: vec2<f32>,
     tangent: vec4<f32>
 }
@@ -15,7 +14,8 @@ struct Vertex {
 };
 
 
-fn fern_vertices(t: f32, vertex: Vertex) -> FernResult {
+// droop bends the fronds down, curl rolls the leaves up; both in [0, 1]
+fn fern_vertices(t: f32, vertex: Vertex, droop: f32, curl: f32) -> FernResult {
     let vertices_per_leaf: u32 = u32(12);
 
     var fi = f32(vertex.vertex_index % vertices_per_leaf) - 1.0;
@@ -44,7 +44,7 @@ fn fern_vertices(t: f32, vertex: Vertex) -> FernResult {
     // length of the leaf; varies slightly per leaf
     let l = 12.0 / f32(vertices_per_leaf) + sin(leaf + 100.0) * 0.1;
     // first leafs are bent more
-    let bendStrength = -2.0 / f32(vertices_per_leaf);
+    let bendStrength = -2.0 * (1.0 + droop * 2.0) / f32(vertices_per_leaf);
 
     //let tooth = (f32(vertex.vertex_index) / 2.0) % 2.0;
     //if tooth <= 0.1 || tooth >= 1.4 {
@@ -61,10 +61,14 @@ fn fern_vertices(t: f32, vertex: Vertex) -> FernResult {
     yaw += wind * 0.03;
 
     let lr = fi % 2.0 - 0.5;
-    pos.x = -lr * shape * w;
+    pos.x = -lr * shape * w * (1.0 - curl * 0.5);
     let bentPitch = pitch + dist * (bendStrength + wind * 0.01);
     pos.y += cos(bentPitch) * dist * l;
     pos.z += sin(bentPitch) * dist * l;
+    // lift the edges of curled leaves along the normal
+    let lift = abs(lr) * curl * w;
+    pos.y += cos(bentPitch + radians(90.0)) * lift;
+    pos.z += sin(bentPitch + radians(90.0)) * lift;
 
     // rotate around the y axis
     let yaw_rotation = mat2x2<f32>(cos(yaw), sin(yaw), -sin(yaw), cos(yaw));
@@ -85,7 +89,8 @@ fn fern_vertices(t: f32, vertex: Vertex) -> FernResult {
     normal.x = rr.x;
     normal.z = rr.y;
 
-    var tangent = vec4<f32>(0.0, cos(bentPitch), sin(bentPitch), 1.0);
+    // the u coordinate decreases along the leaf
+    var tangent = vec4<f32>(0.0, -cos(bentPitch), -sin(bentPitch), 1.0);
     let rrr = tangent.xz * yaw_rotation;
     tangent.x = rrr.x;
     tangent.z = rrr.y;
@@ -93,8 +98,7 @@ fn fern_vertices(t: f32, vertex: Vertex) -> FernResult {
     pos *= 0.2;
     pos.y -= 0.5;
 
-    let ao = clamp(pow(rfi, 3.0) * 3.0 - 0.15, 0.04, 1.0);
     let uv = vec2<f32>(1.0 - rfi, lr + 0.5);
 
-    return FernResult(pos, normal, ao, uv, tangent);
+    return FernResult(pos, normal, uv, tangent);
 }
\ No newline at end of file
@@ -0,0 +1,71 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_view_bindings::{lights, view},
+}
+
+struct CustomMaterial {
+    time: f32,
+    // (offset, size) of the atlas region of each variant
+    uv_rects: array<vec4<f32>, 16>,
+    translucency: f32,
+    droop: f32,
+    curl: f32,
+    sdf: u32,
+    capture: u32,
+};
+
+@group(2) @binding(100) var<uniform> material: CustomMaterial;
+@group(2) @binding(101) var thickness_texture: texture_2d<f32>;
+@group(2) @binding(102) var thickness_sampler: sampler;
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    // red: thickness, green: baked ambient occlusion
+    let mask = textureSample(thickness_texture, thickness_sampler, in.uv);
+    let thickness = mask.r;
+
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    if material.sdf != 0u {
+        // reconstruct a sharp edge from the distance field, one pixel wide at any zoom
+        let d = pbr_input.material.base_color.a;
+        let w = max(fwidth(d), 0.0001);
+        pbr_input.material.base_color.a = smoothstep(0.5 - w, 0.5 + w, d);
+    }
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+    pbr_input.material.base_color = vec4<f32>(pbr_input.material.base_color.rgb * mask.g, pbr_input.material.base_color.a);
+
+    var out: FragmentOutput;
+    // impostor captures: the albedo, world space normals or the depth of the orthographic view
+    if material.capture == 3u {
+        out.color = pbr_input.material.base_color;
+        return out;
+    }
+    if material.capture == 1u {
+        out.color = vec4<f32>(pbr_input.N * 0.5 + 0.5, 1.0);
+        return out;
+    }
+    if material.capture == 2u {
+        out.color = vec4<f32>(vec3<f32>(in.position.z), 1.0);
+        return out;
+    }
+    out.color = apply_pbr_lighting(pbr_input);
+
+    // cheap transmission: light hitting the far side of the leaf shines through
+    // where the leaf is thin. The normal always faces the viewer.
+    var transmitted = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.n_directional_lights; i = i + 1u) {
+        let light = lights.directional_lights[i];
+        let back = max(dot(-pbr_input.N, light.direction_to_light), 0.0);
+        let behind = max(dot(-pbr_input.V, light.direction_to_light), 0.0);
+        transmitted += light.color.rgb * back * (0.5 + 0.5 * behind);
+    }
+    out.color += vec4<f32>(
+        pbr_input.material.base_color.rgb * transmitted * material.translucency * (1.0 - thickness) * view.exposure,
+        0.0
+    );
+
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
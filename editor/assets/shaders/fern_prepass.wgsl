@@ -7,14 +7,21 @@
 
 struct CustomMaterial {
     time: f32,
+    // (offset, size) of the atlas region of each variant
+    uv_rects: array<vec4<f32>, 16>,
+    translucency: f32,
+    droop: f32,
+    curl: f32,
+    sdf: u32,
+    capture: u32,
 };
 
-@group(1) @binding(100) var<uniform> material: CustomMaterial;
+@group(2) @binding(100) var<uniform> material: CustomMaterial;
 
 
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
-    let res = fern_vertices(globals.time, vertex);
+    let res = fern_vertices(globals.time, vertex, material.droop, material.curl);
     let model = get_model_matrix(vertex.instance_index);
     var out: VertexOutput;
     out.position = mesh_position_local_to_clip(model, vec4<f32>(res.pos, 1.0));
@@ -22,6 +29,8 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     out.clip_position_unclamped = out.position;
     out.position.z = min(out.position.z, 1.0);
     out.instance_index = vertex.instance_index;
-    out.uv = res.uv;
+    // the variant is passed in the otherwise unused x coordinate
+    let rect = material.uv_rects[min(u32(vertex.position.x), 15u)];
+    out.uv = rect.xy + res.uv * rect.zw;
     return out;
 }
@@ -0,0 +1,124 @@
+#import bevy_pbr::{
+    mesh_functions::get_model_matrix,
+    mesh_view_bindings::view,
+    pbr_types,
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+
+struct ImpostorMaterial {
+    radius: f32,
+    frames: u32,
+};
+
+@group(2) @binding(0) var<uniform> material: ImpostorMaterial;
+@group(2) @binding(1) var color_texture: texture_2d<f32>;
+@group(2) @binding(2) var color_sampler: sampler;
+@group(2) @binding(3) var normal_texture: texture_2d<f32>;
+@group(2) @binding(4) var normal_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+};
+
+struct ImpostorVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) center: vec3<f32>,
+    @location(2) radius: f32,
+};
+
+// must match `frame_transform` in impostor.rs
+fn frame_basis(dir: vec3<f32>) -> mat2x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(dir.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, dir));
+    return mat2x3<f32>(right, cross(dir, right));
+}
+
+fn hemi_octahedral_encode(dir: vec3<f32>) -> vec2<f32> {
+    let d = vec3<f32>(dir.x, max(dir.y, 0.0), dir.z);
+    let p = d.xz / max(abs(d.x) + abs(d.y) + abs(d.z), 0.0001);
+    return vec2<f32>(p.x + p.y, p.x - p.y);
+}
+
+fn hemi_octahedral_decode(uv: vec2<f32>) -> vec3<f32> {
+    let p = vec2<f32>(uv.x + uv.y, uv.x - uv.y) * 0.5;
+    return normalize(vec3<f32>(p.x, 1.0 - abs(p.x) - abs(p.y), p.y));
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> ImpostorVertexOutput {
+    let model = get_model_matrix(vertex.instance_index);
+    let center = (model * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    let radius = material.radius * length(model[0].xyz);
+
+    // turn the quad towards the camera
+    let basis = frame_basis(normalize(view.world_position - center));
+    let world = center + (basis[0] * vertex.position.x + basis[1] * vertex.position.y) * radius;
+
+    var out: ImpostorVertexOutput;
+    out.position = view.view_proj * vec4<f32>(world, 1.0);
+    out.world_position = vec4<f32>(world, 1.0);
+    out.center = center;
+    out.radius = radius;
+    return out;
+}
+
+struct Sample {
+    color: vec4<f32>,
+    normal: vec3<f32>,
+};
+
+// samples the frame `cell` at the point of the quad
+fn sample_frame(cell: vec2<f32>, local: vec3<f32>, radius: f32) -> Sample {
+    let frames = f32(material.frames);
+    let dir = hemi_octahedral_decode((cell + 0.5) / frames * 2.0 - 1.0);
+    let basis = frame_basis(dir);
+    let uv = vec2<f32>(dot(local, basis[0]), -dot(local, basis[1])) / (2.0 * radius) + 0.5;
+    let atlas_uv = (cell + clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0))) / frames;
+    var s: Sample;
+    s.color = textureSample(color_texture, color_sampler, atlas_uv);
+    s.normal = textureSample(normal_texture, normal_sampler, atlas_uv).xyz * 2.0 - 1.0;
+    return s;
+}
+
+@fragment
+fn fragment(in: ImpostorVertexOutput) -> @location(0) vec4<f32> {
+    let frames = f32(material.frames);
+    let local = in.world_position.xyz - in.center;
+
+    // blend the four frames around the view direction
+    let to_camera = normalize(view.world_position - in.center);
+    let grid = (hemi_octahedral_encode(to_camera) * 0.5 + 0.5) * frames - 0.5;
+    let base = floor(grid);
+    let f = grid - base;
+    var color = vec4<f32>(0.0);
+    var normal = vec3<f32>(0.0);
+    for (var k = 0u; k < 4u; k = k + 1u) {
+        let offset = vec2<f32>(f32(k % 2u), f32(k / 2u));
+        let cell = clamp(base + offset, vec2<f32>(0.0), vec2<f32>(frames - 1.0));
+        let w = mix(1.0 - f.x, f.x, offset.x) * mix(1.0 - f.y, f.y, offset.y);
+        let s = sample_frame(cell, local, in.radius);
+        color += s.color * w;
+        normal += s.normal * w * s.color.a;
+    }
+    if color.a < 0.5 {
+        discard;
+    }
+
+    var pbr_input = pbr_types::pbr_input_new();
+    pbr_input.material.base_color = vec4<f32>(color.rgb / max(color.a, 0.0001), 1.0);
+    pbr_input.material.perceptual_roughness = 0.5;
+    pbr_input.material.metallic = 0.0;
+    pbr_input.frag_coord = in.position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = normalize(normal + to_camera * 0.0001);
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = to_camera;
+    var out = apply_pbr_lighting(pbr_input);
+    out = main_pass_post_lighting_processing(pbr_input, out);
+    return out;
+}
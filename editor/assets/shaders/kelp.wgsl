@@ -0,0 +1,19 @@
+#import bevy_pbr::mesh_functions::{get_model_matrix, mesh_position_local_to_world,mesh_position_local_to_clip}
+#import "shaders/kelp_config.wgsl"::{kelp_vertices, Vertex, KelpMaterial}
+#import bevy_pbr::{forward_io::VertexOutput, mesh_view_bindings::globals}
+
+@group(2) @binding(100) var<uniform> material: KelpMaterial;
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let res = kelp_vertices(globals.time, vertex, material);
+    let model = get_model_matrix(vertex.instance_index);
+    var out = VertexOutput();
+    out.position = mesh_position_local_to_clip(model, vec4<f32>(res.pos, 1.0));
+    out.world_position = mesh_position_local_to_world(model, vec4<f32>(res.pos, 1.0));
+    out.world_normal = (model * vec4<f32>(res.normal, 0.0)).xyz;
+    out.color = vec4<f32>(res.ao, res.ao, res.ao, 1.0);
+    out.instance_index = vertex.instance_index;
+    out.uv = res.uv;
+    return out;
+}
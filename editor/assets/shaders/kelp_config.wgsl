@@ -0,0 +1,86 @@
+struct KelpResult {
+    pos: vec3<f32>,
+    normal: vec3<f32>,
+    ao: f32,
+    uv: vec2<f32>,
+    tangent: vec4<f32>
+}
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) position: vec3<f32>,
+};
+
+struct KelpMaterial {
+    time: f32,
+    depth: f32,
+    current: vec2<f32>,
+    buoyancy: f32,
+    fronds: u32,
+    frond_width: f32,
+};
+
+fn kelp_vertices(t: f32, vertex: Vertex, material: KelpMaterial) -> KelpResult {
+    let vertices_per_blade: u32 = u32(24);
+
+    var fi = f32(vertex.vertex_index % vertices_per_blade) - 1.0;
+    let vpb3 = f32(vertices_per_blade - 3u);
+    if fi <= 0.0 {
+        fi = 0.0;
+    } else if fi >= vpb3 {
+        fi = vpb3;
+    }
+    let blade = floor(f32(vertex.vertex_index) / f32(vertices_per_blade));
+
+    let dist = floor(fi / 2.0);
+    let rfi = dist / f32(vertices_per_blade - 4u) * 2.0;
+    let lr = fi % 2.0 - 0.5;
+
+    // width and length of the blade; length varies slightly per blade
+    let w = material.frond_width;
+    let l = 4.0 + sin(blade * 12.9 + 100.0) * 0.8;
+
+    // blades grow from the holdfast arranged along the golden angle
+    let golden_angle = 2.39996322972865332;
+    let yaw = golden_angle * blade;
+    let spread = sqrt(blade / f32(max(material.fronds, 1u)));
+    let base = vec2<f32>(cos(yaw), sin(yaw)) * 0.2 * spread;
+
+    // deep water dampens the surge, so deep kelp moves slowly and lazily
+    let damping = 1.0 / (1.0 + max(material.depth, 0.0) * 0.1);
+    let phase = t * 0.6 * damping - rfi * 2.0 - blade * 0.7;
+    let surge = sin(phase) + 0.5 * sin(phase * 0.37 + 1.3);
+
+    // buoyancy keeps the blade upright while the current drags it along
+    let strength = length(material.current);
+    var dir = vec2<f32>(1.0, 0.0);
+    if strength > 0.0001 {
+        dir = material.current / strength;
+    }
+    let drag = (strength + surge * 0.3 * strength + surge * 0.05) * damping;
+    let bend = atan(rfi * drag / max(material.buoyancy, 0.01));
+    let sway = vec2<f32>(-dir.y, dir.x) * sin(phase * 1.3) * 0.1 * rfi * damping;
+
+    let h = rfi * l;
+    let center = base + dir * sin(bend) * h + sway;
+    let across = vec3<f32>(cos(yaw), 0.0, sin(yaw));
+
+    var pos = vertex.position;
+    pos.x += center.x + across.x * lr * w;
+    pos.y += cos(bend) * h;
+    pos.z += center.y + across.z * lr * w;
+
+    let along = normalize(vec3<f32>(dir.x * sin(bend), cos(bend), dir.y * sin(bend)));
+    let normal = normalize(cross(across, along));
+    let tangent = vec4<f32>(along, 1.0);
+
+    pos *= 0.2;
+    pos.y -= 0.5;
+
+    // light is absorbed near the sea floor
+    let ao = clamp(0.3 + rfi * 0.7, 0.04, 1.0);
+    let uv = vec2<f32>(1.0 - rfi, lr + 0.5);
+
+    return KelpResult(pos, normal, ao, uv, tangent);
+}
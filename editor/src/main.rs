@@ -5,11 +5,17 @@ use bevy::{
     render::{render_asset::RenderAssetUsages, Render},
     window::WindowResolution,
 };
-use bevy_inspector_egui::quick::FilterQueryInspectorPlugin;
+use bevy_inspector_egui::{
+    bevy_egui::{egui, EguiContexts},
+    quick::FilterQueryInspectorPlugin,
+};
 use bevy_panorbit_camera::*;
 use bevy_procedural_vegetation::{
-    components::{render_texture, FernMaterial, FernSettings, VegetationPlugin},
+    components::{render_texture, BakeSetup, Fern, FernMaterial, FernSettings, VegetationPlugin},
+    export::{ExportBake, ExportFormat},
     fern::{fern_mesh, FernPart},
+    generator::{BakeTarget, VegetationGenerator},
+    impostor::ImpostorPlugin,
     *,
 };
 use std::{env, f32::consts::PI};
@@ -34,9 +40,11 @@ pub fn main() {
     })*/
     .add_plugins((
         MaterialPlugin::<ExtendedMaterial<StandardMaterial, FernMaterial>>::default(),
-        VegetationPlugin,
+        VegetationPlugin::<Fern>::default(),
+        ImpostorPlugin,
     ))
     .register_type::<FernSettings>()
+    .register_type::<BakeTarget>()
     .add_systems(Startup, setup_scene)
     .add_plugins((
         FrameTimeDiagnosticsPlugin,
@@ -45,34 +53,41 @@ pub fn main() {
         PanOrbitCameraPlugin,
     ));
 
-    app.add_systems(Update, (update_vegetation, bevy::window::close_on_esc));
+    app.add_systems(Update, (export_ui, bevy::window::close_on_esc));
 
     app.run();
 }
 
-fn setup_scene(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut color_materials: ResMut<Assets<ColorMaterial>>,
-    mut standard_materials: ResMut<Assets<StandardMaterial>>,
-    mut images: ResMut<Assets<Image>>,
+fn export_ui(
+    mut contexts: EguiContexts,
+    mut dir: Local<Option<String>>,
+    mut format: Local<ExportFormat>,
+    mut exports: EventWriter<ExportBake<Fern>>,
 ) {
+    let dir = dir.get_or_insert_with(|| "baked".to_string());
+    egui::Window::new("Export").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Directory");
+            ui.text_edit_singleline(dir);
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut *format, ExportFormat::Png, "PNG");
+            ui.radio_value(&mut *format, ExportFormat::Exr, "EXR");
+            ui.radio_value(&mut *format, ExportFormat::Ktx2, "KTX2");
+        });
+        if ui.button("Export").clicked() {
+            exports.send(ExportBake::new(dir.as_str(), *format));
+        }
+    });
+}
+
+fn setup_scene(mut setup: BakeSetup, mut standard_materials: ResMut<Assets<StandardMaterial>>) {
     // TODO: use instancing https://github.com/bevyengine/bevy/blob/release-0.12.1/examples/shader/shader_instancing.rs#L104
 
-    render_texture(
-        2048,
-        512,
-        &mut commands,
-        &mut meshes,
-        &mut color_materials,
-        &mut images,
-        [
-            Color::rgb(0.1, 0.2, 0.0),
-            Color::rgb(0.05, 0.3, 0.0),
-            Color::rgb(0.05, 0.36, 0.05),
-        ],
-        1,
-    );
+    render_texture::<Fern>(&mut setup, "fern", 2048, 512, &Fern::palettes());
+    let BakeSetup {
+        commands, meshes, ..
+    } = &mut setup;
 
     /*
     let fern = fern_mesh(&FernSettings::default(), FernPart::Stem);
@@ -0,0 +1,52 @@
+use bevy::{log::LogPlugin, prelude::*};
+use bevy_procedural_vegetation::{
+    atlas::AtlasBuilder,
+    components::{Fern, FernSettings},
+    export::{export_image, ExportFormat},
+    generator::BakeTarget,
+};
+use std::path::Path;
+
+fn main() {
+    // Bakes ferns of different shapes on the CPU and packs them into one atlas per map.
+    App::new()
+        .add_plugins(LogPlugin::default())
+        .add_systems(Startup, bake)
+        .run();
+}
+
+fn bake() {
+    let mut builder = AtlasBuilder::new(2048);
+    for (name, leaflets1, curvature) in [
+        ("young", 20, 0.01),
+        ("grown", 35, 0.014),
+        ("drooping", 35, 0.03),
+    ] {
+        let settings = FernSettings {
+            target: BakeTarget::new(1024, 256),
+            leaflets1,
+            curvature,
+            ..default()
+        };
+        builder.add::<Fern>(name, &settings);
+    }
+    let atlas = builder.build();
+
+    let dir = Path::new("baked");
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        error!("Failed to create {:?}: {}", dir, err);
+        return;
+    }
+    for (map, img) in &atlas.maps {
+        let path = dir.join(format!("ferns_{}.png", map));
+        match export_image(img, &path, ExportFormat::Png) {
+            Ok(()) => info!("Wrote {:?}", path),
+            Err(err) => error!("Failed to export the {} atlas: {}", map, err),
+        }
+    }
+    // the regions for the uv_rects of the FernMaterial, selected by make_fern_variant_mesh
+    match atlas.uv_rects() {
+        Ok(rects) => info!("Regions: {:?}", &rects[..atlas.rects.len()]),
+        Err(err) => error!("{}", err),
+    }
+}
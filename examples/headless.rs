@@ -0,0 +1,35 @@
+use bevy::{log::LogPlugin, prelude::*};
+use bevy_procedural_vegetation::{
+    components::{Fern, FernSettings},
+    export::{export_maps, ExportFormat},
+    generator::BakeTarget,
+    raster::bake_headless,
+};
+use std::path::Path;
+
+fn main() {
+    // Bakes the fern on the CPU and writes the maps to disk. No window or GPU required.
+    App::new()
+        .add_plugins(LogPlugin::default())
+        .add_systems(Startup, bake)
+        .run();
+}
+
+fn bake() {
+    let settings = FernSettings {
+        target: BakeTarget::new(2048, 512),
+        ..default()
+    };
+    let maps = bake_headless::<Fern>(&settings, 4);
+    let maps: Vec<(&str, &Image)> = maps.iter().map(|(name, img)| (*name, img)).collect();
+    match export_maps(
+        Path::new("baked"),
+        "fern",
+        &maps,
+        &settings,
+        ExportFormat::Png,
+    ) {
+        Ok(files) => info!("Wrote {:?}", files),
+        Err(err) => error!("Failed to export the fern: {}", err),
+    }
+}
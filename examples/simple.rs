@@ -1,9 +1,8 @@
 use bevy::prelude::*;
-use bevy_procedural_vegetation::*;
+use bevy_procedural_vegetation::components::{Fern, VegetationPlugin};
 
 fn main() {
     App::new()
-        .add_plugins(DefaultPlugins)
-        .add_systems(Update, update_vegetation)
+        .add_plugins((DefaultPlugins, VegetationPlugin::<Fern>::default()))
         .run();
 }
@@ -0,0 +1,289 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension},
+    },
+};
+
+use crate::{
+    components::MAX_ATLAS_VARIANTS, generator::VegetationGenerator, raster::bake_headless,
+};
+
+/// A single variant waiting to be packed.
+struct AtlasEntry {
+    name: String,
+    maps: Vec<(&'static str, Image)>,
+}
+
+/// Packs the bakes of many plant variants, possibly of different species,
+/// into one texture per map so their instances can share a single material.
+pub struct AtlasBuilder {
+    entries: Vec<AtlasEntry>,
+    max_width: u32,
+    padding: u32,
+    samples: u32,
+}
+
+impl AtlasBuilder {
+    /// Rows of the atlas are at most `max_width` pixels wide.
+    pub fn new(max_width: u32) -> Self {
+        AtlasBuilder {
+            entries: vec![],
+            max_width,
+            padding: 4,
+            samples: 4,
+        }
+    }
+
+    /// Empty pixels between the variants to avoid bleeding when filtering.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Anti-aliasing of the variants baked by [`AtlasBuilder::add`].
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Bakes a variant of the species `G` on the CPU and adds it under `name`.
+    pub fn add<G: VegetationGenerator>(&mut self, name: &str, settings: &G::Settings) -> &mut Self {
+        let maps = bake_headless::<G>(settings, self.samples);
+        self.add_maps(name, maps)
+    }
+
+    /// Adds an already baked variant, e.g., the maps of [`crate::export::Baked`].
+    /// All images must use the same 8 bit format as the other variants.
+    pub fn add_maps(&mut self, name: &str, maps: Vec<(&'static str, Image)>) -> &mut Self {
+        self.entries.push(AtlasEntry {
+            name: name.to_string(),
+            maps,
+        });
+        self
+    }
+
+    /// Packs the variants using shelf packing: the variants are sorted by
+    /// height and placed left to right in rows.
+    pub fn build(self) -> VegetationAtlas {
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.entries[i].size().y));
+
+        let mut offsets = vec![UVec2::ZERO; self.entries.len()];
+        let (mut x, mut y, mut row_height, mut width) = (0, 0, 0, 0);
+        for i in order {
+            let size = self.entries[i].size() + UVec2::splat(self.padding);
+            if x > 0 && x + size.x > self.max_width {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            offsets[i] = UVec2::new(x, y);
+            x += size.x;
+            row_height = row_height.max(size.y);
+            width = width.max(x);
+        }
+        let size = UVec2::new(width, y + row_height).max(UVec2::ONE);
+        let size = UVec2::new(size.x.next_power_of_two(), size.y.next_power_of_two());
+
+        // every map present in any variant gets its own texture
+        let mut names: Vec<&'static str> = vec![];
+        for entry in &self.entries {
+            for (map, _) in &entry.maps {
+                if !names.contains(map) {
+                    names.push(map);
+                }
+            }
+        }
+
+        let maps = names
+            .into_iter()
+            .map(|map| {
+                let mut atlas: Option<Image> = None;
+                for (entry, offset) in self.entries.iter().zip(&offsets) {
+                    let Some((_, img)) = entry.maps.iter().find(|(m, _)| *m == map) else {
+                        continue;
+                    };
+                    let atlas = atlas.get_or_insert_with(|| empty_map(map, size, img));
+                    blit(atlas, img, *offset);
+                }
+                (map, atlas.unwrap())
+            })
+            .collect();
+
+        let rects = self
+            .entries
+            .iter()
+            .zip(offsets)
+            .map(|(entry, offset)| {
+                let min = offset.as_vec2() / size.as_vec2();
+                let max = (offset + entry.size()).as_vec2() / size.as_vec2();
+                (entry.name.clone(), Rect::from_corners(min, max))
+            })
+            .collect();
+
+        VegetationAtlas { maps, rects }
+    }
+}
+
+impl AtlasEntry {
+    fn size(&self) -> UVec2 {
+        self.maps
+            .first()
+            .map(|(_, img)| img.size())
+            .unwrap_or(UVec2::ZERO)
+    }
+}
+
+/// An empty texture for the map `map` in the format of `like`. Normal maps are
+/// filled with the flat normal.
+fn empty_map(map: &str, size: UVec2, like: &Image) -> Image {
+    let pixel = if map == "normal" {
+        [128, 128, 255, 255]
+    } else {
+        [0, 0, 0, 0]
+    };
+    Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &pixel,
+        like.texture_descriptor.format,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Copies the pixels of `img` into `atlas` with the top left corner at `offset`.
+fn blit(atlas: &mut Image, img: &Image, offset: UVec2) {
+    let atlas_width = atlas.width() as usize;
+    let row = img.width() as usize * 4;
+    for y in 0..img.height() as usize {
+        let start = ((offset.y as usize + y) * atlas_width + offset.x as usize) * 4;
+        atlas.data[start..start + row].copy_from_slice(&img.data[y * row..(y + 1) * row]);
+    }
+}
+
+/// The packed maps of an [`AtlasBuilder`] together with the region of every variant.
+pub struct VegetationAtlas {
+    /// The packed textures by map name, see [`crate::export::Baked::maps`].
+    pub maps: Vec<(&'static str, Image)>,
+    /// The region of each variant in uv space in the order they were added.
+    pub rects: Vec<(String, Rect)>,
+}
+
+impl VegetationAtlas {
+    /// The packed texture of the map `map`.
+    pub fn map(&self, map: &str) -> Option<&Image> {
+        self.maps
+            .iter()
+            .find(|(m, _)| *m == map)
+            .map(|(_, img)| img)
+    }
+
+    /// Index of the variant `name`. Pass it to [`crate::components::make_fern_variant_mesh`].
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.rects.iter().position(|(n, _)| n == name)
+    }
+
+    /// The region of the variant `name` in uv space.
+    pub fn uv_rect(&self, name: &str) -> Option<Rect> {
+        self.rects
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, rect)| *rect)
+    }
+
+    /// The table of regions as expected by [`crate::components::FernMaterial::uv_rects`].
+    /// Fails if the atlas has more variants than the material supports.
+    pub fn uv_rects(&self) -> Result<[Vec4; MAX_ATLAS_VARIANTS], String> {
+        if self.rects.len() > MAX_ATLAS_VARIANTS {
+            return Err(format!(
+                "The atlas has {} variants but the material only supports {}",
+                self.rects.len(),
+                MAX_ATLAS_VARIANTS
+            ));
+        }
+        let mut table = [Vec4::new(0.0, 0.0, 1.0, 1.0); MAX_ATLAS_VARIANTS];
+        for (entry, (_, rect)) in table.iter_mut().zip(&self.rects) {
+            *entry = rect.min.extend(rect.size().x).extend(rect.size().y);
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_images::filled;
+    use bevy::render::render_resource::TextureFormat;
+
+    fn variant(width: u32, height: u32, texel: [u8; 4]) -> Vec<(&'static str, Image)> {
+        let size = UVec2::new(width, height);
+        vec![("color", filled(size, TextureFormat::Rgba8UnormSrgb, texel))]
+    }
+
+    fn texel(img: &Image, x: u32, y: u32) -> &[u8] {
+        &img.data[((y * img.width() + x) * 4) as usize..][..4]
+    }
+
+    /// Three variants in a 16 texels wide atlas: the tallest first, the last
+    /// one doesn't fit into the first row anymore.
+    fn atlas() -> VegetationAtlas {
+        let mut builder = AtlasBuilder::new(16).with_padding(2);
+        let mut tall = variant(8, 8, [255, 0, 0, 255]);
+        tall.push((
+            "normal",
+            filled(UVec2::new(8, 8), TextureFormat::Rgba8Unorm, [0, 0, 0, 255]),
+        ));
+        builder
+            .add_maps("tall", tall)
+            .add_maps("small", variant(4, 4, [0, 255, 0, 255]))
+            .add_maps("wide", variant(8, 4, [0, 0, 255, 255]));
+        builder.build()
+    }
+
+    #[test]
+    fn variants_are_packed_in_shelves() {
+        let atlas = atlas();
+        let rect = |name| atlas.uv_rect(name).unwrap();
+        assert_eq!(rect("tall"), Rect::new(0.0, 0.0, 0.5, 0.5));
+        assert_eq!(rect("small"), Rect::new(0.625, 0.0, 0.875, 0.25));
+        assert_eq!(rect("wide"), Rect::new(0.0, 0.625, 0.5, 0.875));
+        assert_eq!(atlas.index("wide"), Some(2));
+    }
+
+    #[test]
+    fn variants_are_blitted_to_their_regions() {
+        let atlas = atlas();
+        let color = atlas.map("color").unwrap();
+        assert_eq!(color.size(), UVec2::new(16, 16));
+        assert_eq!(texel(color, 7, 7), [255, 0, 0, 255]);
+        assert_eq!(texel(color, 10, 0), [0, 255, 0, 255]);
+        assert_eq!(texel(color, 7, 13), [0, 0, 255, 255]);
+        // the padding stays transparent
+        assert_eq!(texel(color, 9, 0), [0, 0, 0, 0]);
+        assert_eq!(texel(color, 14, 0), [0, 0, 0, 0]);
+
+        // variants without a map get the flat normal
+        let normal = atlas.map("normal").unwrap();
+        assert_eq!(texel(normal, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(texel(normal, 10, 0), [128, 128, 255, 255]);
+    }
+
+    #[test]
+    fn uv_rects_fit_the_material() {
+        let table = atlas().uv_rects().unwrap();
+        assert_eq!(table[1], Vec4::new(0.625, 0.0, 0.25, 0.25));
+        assert_eq!(table[3], Vec4::new(0.0, 0.0, 1.0, 1.0));
+
+        let mut builder = AtlasBuilder::new(1024);
+        for i in 0..=MAX_ATLAS_VARIANTS {
+            builder.add_maps(&i.to_string(), variant(4, 4, [255; 4]));
+        }
+        assert!(builder.build().uv_rects().is_err());
+    }
+}
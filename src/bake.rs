@@ -0,0 +1,587 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{ImageSampler, ImageSamplerDescriptor},
+    },
+};
+
+/// Reads the red channel of an RGBA8 image as a height field in `[0, 1]`.
+/// Transparent texels have a height of zero.
+pub fn height_field(img: &Image) -> Vec<f32> {
+    img.data
+        .chunks_exact(4)
+        .map(|p| p[0] as f32 / 255.0 * p[3] as f32 / 255.0)
+        .collect()
+}
+
+/// Box blurs a height field with the given radius in texels to turn hard
+/// masks into smooth ridges.
+pub fn blur(height: &[f32], width: usize, radius: usize) -> Vec<f32> {
+    if radius == 0 {
+        return height.to_vec();
+    }
+    let rows = height.len() / width;
+    let pass = |src: &[f32], horizontal: bool| -> Vec<f32> {
+        let mut dst = vec![0.0; src.len()];
+        for y in 0..rows {
+            for x in 0..width {
+                let mut sum = 0.0;
+                let mut count = 0.0;
+                for k in -(radius as isize)..=(radius as isize) {
+                    let (sx, sy) = if horizontal {
+                        (x as isize + k, y as isize)
+                    } else {
+                        (x as isize, y as isize + k)
+                    };
+                    if sx >= 0 && sy >= 0 && (sx as usize) < width && (sy as usize) < rows {
+                        sum += src[sy as usize * width + sx as usize];
+                        count += 1.0;
+                    }
+                }
+                dst[y * width + x] = sum / count;
+            }
+        }
+        dst
+    };
+    pass(&pass(height, true), false)
+}
+
+/// Derives a tangent-space normal map from a height field using central
+/// differences. The green channel points up in the image.
+pub fn normal_from_height(height: &[f32], width: u32, height_px: u32, strength: f32) -> Image {
+    let (w, h) = (width as usize, height_px as usize);
+    let at = |x: isize, y: isize| -> f32 {
+        let x = x.clamp(0, w as isize - 1) as usize;
+        let y = y.clamp(0, h as isize - 1) as usize;
+        height[y * w + x]
+    };
+    let mut data = Vec::with_capacity(w * h * 4);
+    for y in 0..h as isize {
+        for x in 0..w as isize {
+            let dx = (at(x + 1, y) - at(x - 1, y)) * 0.5 * strength;
+            // rows go downwards, but the green channel points up
+            let dy = (at(x, y - 1) - at(x, y + 1)) * 0.5 * strength;
+            let n = Vec3::new(-dx, -dy, 1.0).normalize();
+            data.extend_from_slice(&[
+                ((n.x * 0.5 + 0.5) * 255.0) as u8,
+                ((n.y * 0.5 + 0.5) * 255.0) as u8,
+                ((n.z * 0.5 + 0.5) * 255.0) as u8,
+                255,
+            ]);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width,
+            height: height_px,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Ambient occlusion of a height field. Texels surrounded by higher texels
+/// within `radius`, e.g., leaflets next to the stem, are darkened. Returns
+/// the visibility in `[0, 1]`.
+pub fn ambient_occlusion(height: &[f32], width: usize, radius: usize, strength: f32) -> Vec<f32> {
+    let surrounding = blur(height, width, radius);
+    height
+        .iter()
+        .zip(surrounding)
+        .map(|(h, s)| 1.0 - ((s - h).max(0.0) * strength).min(1.0))
+        .collect()
+}
+
+/// Packs two fields in `[0, 1]` into the red and green channel of an RGBA8
+/// mask image, e.g., thickness and ambient occlusion.
+pub fn mask_image(red: &[f32], green: &[f32], width: u32, height: u32) -> Image {
+    let data = red
+        .iter()
+        .zip(green)
+        .flat_map(|(r, g)| {
+            let r = (r.clamp(0.0, 1.0) * 255.0) as u8;
+            let g = (g.clamp(0.0, 1.0) * 255.0) as u8;
+            [r, g, 0, 255]
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Fraction of the texels that pass the alpha mask `threshold`.
+fn alpha_coverage(texels: &[Vec4], threshold: f32) -> f32 {
+    let passing = texels.iter().filter(|t| t.w >= threshold).count();
+    passing as f32 / texels.len().max(1) as f32
+}
+
+/// The smallest alpha scale, up to 4, after which a `coverage` fraction of the
+/// texels passes the alpha mask `threshold` once stored with 8 bits.
+fn coverage_scale(texels: &[Vec4], threshold: f32, coverage: f32) -> f32 {
+    let passing = ((coverage * texels.len() as f32).ceil() as usize).min(texels.len());
+    if passing == 0 {
+        return 1.0;
+    }
+    let mut alphas: Vec<f32> = texels.iter().map(|t| t.w).collect();
+    let (_, alpha, _) = alphas.select_nth_unstable_by(passing - 1, |a, b| b.total_cmp(a));
+    if *alpha <= 0.0 {
+        return 4.0;
+    }
+    // round up to the next 8 bit value so the texel still passes after encoding
+    let threshold = (threshold * 255.0).ceil() / 255.0;
+    (threshold / *alpha).min(4.0)
+}
+
+/// Halves a level with a 2x2 box filter. Colours are averaged premultiplied so
+/// transparent texels don't darken the edges. Fully transparent blocks keep the
+/// plain average to preserve the colours bled by [`dilate`].
+fn downsample(texels: &[Vec4], width: usize, height: usize) -> (Vec<Vec4>, usize, usize) {
+    let (w, h) = ((width / 2).max(1), (height / 2).max(1));
+    let mut out = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = Vec4::ZERO;
+            let mut plain = Vec3::ZERO;
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let sx = (x * 2 + dx).min(width - 1);
+                let sy = (y * 2 + dy).min(height - 1);
+                let t = texels[sy * width + sx];
+                sum += (t.truncate() * t.w).extend(t.w);
+                plain += t.truncate();
+            }
+            let avg = sum / 4.0;
+            let rgb = if avg.w > 0.0 {
+                avg.truncate() / avg.w
+            } else {
+                plain / 4.0
+            };
+            out.push(rgb.extend(avg.w));
+        }
+    }
+    (out, w, h)
+}
+
+/// Filters an 8 bit RGBA image rendered at `factor` times the resolution down
+/// with a box filter. Colours are averaged premultiplied and in linear space.
+pub fn downsample_image(img: &Image, factor: u32) -> Image {
+    let srgb = img.texture_descriptor.format.is_srgb();
+    let decode = |v: u8| {
+        let v = v as f32 / 255.0;
+        if srgb {
+            v.powf(2.2)
+        } else {
+            v
+        }
+    };
+    let encode = |v: f32| {
+        let v = if srgb { v.powf(1.0 / 2.2) } else { v };
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    let (src_w, src_h) = (img.width() as usize, img.height() as usize);
+    let f = factor.max(1) as usize;
+    let (w, h) = ((src_w / f).max(1), (src_h / f).max(1));
+    let mut data = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = Vec4::ZERO;
+            let mut plain = Vec3::ZERO;
+            for sy in (y * f..(y + 1) * f).map(|sy| sy.min(src_h - 1)) {
+                for sx in (x * f..(x + 1) * f).map(|sx| sx.min(src_w - 1)) {
+                    let p = &img.data[(sy * src_w + sx) * 4..][..4];
+                    let rgb = Vec3::new(decode(p[0]), decode(p[1]), decode(p[2]));
+                    let a = p[3] as f32 / 255.0;
+                    sum += (rgb * a).extend(a);
+                    plain += rgb;
+                }
+            }
+            let n = (f * f) as f32;
+            let rgb = if sum.w > 0.0 {
+                sum.truncate() / sum.w
+            } else {
+                plain / n
+            };
+            data.extend_from_slice(&[
+                encode(rgb.x),
+                encode(rgb.y),
+                encode(rgb.z),
+                (sum.w / n * 255.0).round() as u8,
+            ]);
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: w as u32,
+            height: h as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        img.texture_descriptor.format,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Generates the full mip chain of an 8 bit RGBA image. With a `threshold`,
+/// the alpha of every level is scaled such that as many texels pass the alpha
+/// mask as in the full resolution image. Thin leaflets thus don't vanish at distance.
+pub fn generate_mips(img: &Image, threshold: Option<f32>) -> Image {
+    let format = img.texture_descriptor.format;
+    let srgb = format.is_srgb();
+    let decode = |v: u8| {
+        let v = v as f32 / 255.0;
+        if srgb {
+            v.powf(2.2)
+        } else {
+            v
+        }
+    };
+    let encode = |v: f32| {
+        let v = if srgb { v.powf(1.0 / 2.2) } else { v };
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    let (mut width, mut height) = (img.width() as usize, img.height() as usize);
+    let mut texels: Vec<Vec4> = img
+        .data
+        .chunks_exact(4)
+        .take(width * height)
+        .map(|p| {
+            Vec4::new(
+                decode(p[0]),
+                decode(p[1]),
+                decode(p[2]),
+                p[3] as f32 / 255.0,
+            )
+        })
+        .collect();
+    let target = threshold.map(|threshold| (threshold, alpha_coverage(&texels, threshold)));
+
+    let mut data = img.data[..width * height * 4].to_vec();
+    let mut levels = 1;
+    while width > 1 || height > 1 {
+        (texels, width, height) = downsample(&texels, width, height);
+        if let Some((threshold, coverage)) = target {
+            // match the coverage of the first level
+            let scale = coverage_scale(&texels, threshold, coverage);
+            for t in texels.iter_mut() {
+                t.w = (t.w * scale).min(1.0);
+            }
+        }
+        for t in &texels {
+            data.extend_from_slice(&[
+                encode(t.x),
+                encode(t.y),
+                encode(t.z),
+                (t.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+            ]);
+        }
+        levels += 1;
+    }
+
+    let mut mipped = img.clone();
+    mipped.data = data;
+    mipped.texture_descriptor.mip_level_count = levels;
+    mipped.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor::linear());
+    mipped
+}
+
+/// Bleeds the colours of an RGBA8 image into the fully transparent texels up
+/// to `texels` away from the silhouette. The alpha stays untouched, but
+/// bilinear filtering and mipmaps no longer pick up the clear colour at the edges.
+pub fn dilate(img: &Image, texels: u32) -> Image {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let mut out = img.clone();
+    let mut filled: Vec<bool> = img
+        .data
+        .chunks_exact(4)
+        .take(w * h)
+        .map(|p| p[3] > 0)
+        .collect();
+
+    for _ in 0..texels {
+        let mut next = filled.clone();
+        let mut changed = false;
+        for y in 0..h {
+            for x in 0..w {
+                if filled[y * w + x] {
+                    continue;
+                }
+                // average the filled neighbours of the previous pass
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for (dx, dy) in [
+                    (-1, 0),
+                    (1, 0),
+                    (0, -1),
+                    (0, 1),
+                    (-1, -1),
+                    (1, -1),
+                    (-1, 1),
+                    (1, 1),
+                ] {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= w as i32 || ny >= h as i32 {
+                        continue;
+                    }
+                    let n = ny as usize * w + nx as usize;
+                    if !filled[n] {
+                        continue;
+                    }
+                    for (c, s) in sum.iter_mut().enumerate() {
+                        *s += out.data[n * 4 + c] as u32;
+                    }
+                    count += 1;
+                }
+                if count == 0 {
+                    continue;
+                }
+                for (c, s) in sum.iter().enumerate() {
+                    out.data[(y * w + x) * 4 + c] = (s / count) as u8;
+                }
+                next[y * w + x] = true;
+                changed = true;
+            }
+        }
+        filled = next;
+        if !changed {
+            break;
+        }
+    }
+    out
+}
+
+/// One dimensional squared euclidean distance transform (Felzenszwalb and Huttenlocher).
+fn distance_transform_1d(f: &[f32]) -> Vec<f32> {
+    let n = f.len();
+    if n == 0 {
+        return vec![];
+    }
+    let mut d = vec![0.0; n];
+    let mut v = vec![0usize; n];
+    let mut z = vec![0.0f32; n + 1];
+    let mut k = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+    let intersection = |q: usize, p: usize| {
+        ((f[q] + (q * q) as f32) - (f[p] + (p * p) as f32)) / (2.0 * q as f32 - 2.0 * p as f32)
+    };
+    for q in 1..n {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    for (q, d) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let dq = q as f32 - v[k] as f32;
+        *d = dq * dq + f[v[k]];
+    }
+    d
+}
+
+/// Squared distance of every texel to the closest texel of the mask.
+fn distance_to(mask: &[bool], width: usize, height: usize) -> Vec<f32> {
+    // large instead of infinite to keep the intersections finite
+    let far = ((width * width + height * height) * 4) as f32;
+    let mut grid: Vec<f32> = mask.iter().map(|&m| if m { 0.0 } else { far }).collect();
+    for x in 0..width {
+        let column: Vec<f32> = (0..height).map(|y| grid[y * width + x]).collect();
+        for (y, d) in distance_transform_1d(&column).into_iter().enumerate() {
+            grid[y * width + x] = d;
+        }
+    }
+    for row in grid.chunks_exact_mut(width) {
+        let d = distance_transform_1d(row);
+        row.copy_from_slice(&d);
+    }
+    grid
+}
+
+/// Signed distance field of the silhouette of an RGBA8 image. The edge lies at
+/// 0.5 and distances of up to `spread` texels are mapped to `[0, 1]`, inside
+/// being above 0.5.
+pub fn signed_distance_field(img: &Image, spread: f32) -> Vec<f32> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let inside: Vec<bool> = img
+        .data
+        .chunks_exact(4)
+        .take(w * h)
+        .map(|p| p[3] >= 128)
+        .collect();
+    let outside: Vec<bool> = inside.iter().map(|i| !i).collect();
+    let to_inside = distance_to(&inside, w, h);
+    let to_outside = distance_to(&outside, w, h);
+    to_inside
+        .into_iter()
+        .zip(to_outside)
+        .map(|(di, do_)| {
+            // texel centers are half a texel away from the edge
+            let d = if di > 0.0 {
+                -(di.sqrt() - 0.5)
+            } else {
+                do_.sqrt() - 0.5
+            };
+            (0.5 + d / (2.0 * spread.max(0.001))).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// Replaces the alpha channel of an RGBA8 image, e.g., with a signed distance field.
+pub fn with_alpha(img: &Image, alpha: &[f32]) -> Image {
+    let mut out = img.clone();
+    for (p, a) in out.data.chunks_exact_mut(4).zip(alpha) {
+        p[3] = (a.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_images::image;
+
+    /// Thin diagonal strokes of varying opacity on a transparent background.
+    fn strokes(size: u32) -> Image {
+        image(UVec2::splat(size), TextureFormat::Rgba8UnormSrgb, |x, y| {
+            let alpha = if (x + y) % 6 == 0 {
+                128 + (x * 31 + y * 17) % 128
+            } else {
+                0
+            } as u8;
+            [40, 120, 20, alpha]
+        })
+    }
+
+    /// The fraction of texels of every mip level passing the alpha mask at 0.5.
+    fn level_coverage(img: &Image) -> Vec<f32> {
+        let mut offset = 0;
+        let mut size = img.width() as usize;
+        let mut levels = vec![];
+        for _ in 0..img.texture_descriptor.mip_level_count {
+            let level = &img.data[offset..offset + size * size * 4];
+            let passing = level.chunks_exact(4).filter(|p| p[3] >= 128).count();
+            levels.push(passing as f32 / (size * size) as f32);
+            offset += size * size * 4;
+            size = (size / 2).max(1);
+        }
+        levels
+    }
+
+    #[test]
+    fn mips_preserve_the_alpha_coverage() {
+        let img = strokes(128);
+        let mipped = generate_mips(&img, Some(0.5));
+        assert_eq!(mipped.texture_descriptor.mip_level_count, 8);
+        let coverage = level_coverage(&mipped);
+        // the levels down to 16×16 keep the coverage of the strokes
+        for level in &coverage[1..4] {
+            assert!((level - coverage[0]).abs() < 0.05, "{:?}", coverage);
+        }
+
+        // without, the strokes fade out
+        let plain = level_coverage(&generate_mips(&img, None));
+        assert!(plain[3] < coverage[0] / 2.0, "{:?}", plain);
+    }
+
+    #[test]
+    fn coverage_scale_reaches_the_threshold() {
+        let texels: Vec<Vec4> = [0.1, 0.2, 0.25, 0.4]
+            .into_iter()
+            .map(|a| Vec4::new(0.0, 0.0, 0.0, a))
+            .collect();
+        let scale = coverage_scale(&texels, 0.5, 0.5);
+        let scaled: Vec<Vec4> = texels.iter().map(|t| *t * scale).collect();
+        assert_eq!(alpha_coverage(&scaled, 0.5), 0.5);
+        assert_eq!(coverage_scale(&texels, 0.5, 0.0), 1.0);
+    }
+
+    #[test]
+    fn sdf_is_signed_and_clamped_to_the_spread() {
+        // the left half is inside
+        let img = image(UVec2::new(16, 4), TextureFormat::Rgba8Unorm, |x, _| {
+            [0, 0, 0, if x < 8 { 255 } else { 0 }]
+        });
+        let row = |spread| signed_distance_field(&img, spread)[..16].to_vec();
+        let sdf = row(4.0);
+        // half a texel from the edge on either side
+        assert_eq!(sdf[7], 0.5625);
+        assert_eq!(sdf[8], 0.4375);
+        assert!(sdf.windows(2).all(|w| w[0] >= w[1]), "{:?}", sdf);
+        assert_eq!(sdf[5], 0.8125);
+        // beyond the spread
+        assert_eq!(sdf[0], 1.0);
+        assert_eq!(sdf[15], 0.0);
+        assert_eq!(row(2.0)[5], 1.0);
+    }
+
+    #[test]
+    fn dilation_fills_the_colour_only() {
+        let img = image(UVec2::new(4, 4), TextureFormat::Rgba8Unorm, |x, y| {
+            if (x, y) == (1, 1) {
+                [200, 100, 0, 255]
+            } else {
+                [0, 0, 0, 0]
+            }
+        });
+        let texel = |img: &Image, x: usize, y: usize| img.data[(y * 4 + x) * 4..][..4].to_vec();
+
+        let once = dilate(&img, 1);
+        assert_eq!(texel(&once, 1, 1), [200, 100, 0, 255]);
+        assert_eq!(texel(&once, 0, 0), [200, 100, 0, 0]);
+        assert_eq!(texel(&once, 2, 1), [200, 100, 0, 0]);
+        // two texels away from the silhouette
+        assert_eq!(texel(&once, 3, 3), [0, 0, 0, 0]);
+        assert_eq!(texel(&dilate(&img, 2), 3, 3), [200, 100, 0, 0]);
+        assert!(once
+            .data
+            .chunks_exact(4)
+            .zip(img.data.chunks_exact(4))
+            .all(|(a, b)| a[3] == b[3]));
+    }
+
+    #[test]
+    fn downsampling_averages_boxes() {
+        let img = image(UVec2::new(4, 4), TextureFormat::Rgba8Unorm, |x, y| {
+            [x as u8 * 50, y as u8 * 50, 0, 255]
+        });
+        let half = downsample_image(&img, 2);
+        assert_eq!(half.size(), UVec2::new(2, 2));
+        assert_eq!(&half.data[..4], [25, 25, 0, 255]);
+        assert_eq!(&half.data[12..], [125, 125, 0, 255]);
+        let quarter = downsample_image(&img, 4);
+        assert_eq!(quarter.data, [75, 75, 0, 255]);
+
+        // transparent texels don't bleed their colour into the average
+        let checker = image(UVec2::new(2, 2), TextureFormat::Rgba8Unorm, |x, y| {
+            if (x + y) % 2 == 0 {
+                [0, 255, 0, 255]
+            } else {
+                [255, 0, 0, 0]
+            }
+        });
+        assert_eq!(downsample_image(&checker, 2).data, [0, 255, 0, 128]);
+    }
+}
@@ -0,0 +1,152 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use image::ImageError;
+use std::path::PathBuf;
+
+use crate::{
+    export::{export_image, settings_ron, ExportFormat},
+    generator::{VegetationGenerator, VegetationSettings},
+    palette::BakePalette,
+};
+
+/// The maps a bake can consist of, see [`crate::export::Baked::maps`].
+const MAPS: [&str; 4] = ["color", "normal", "thickness", "height"];
+
+/// Stores baked maps on disk so they can be loaded instead of re-rendered.
+/// Insert it as resource to enable the cache of the [`crate::components::VegetationPlugin`].
+#[derive(Resource, Debug, Clone)]
+pub struct BakeCache {
+    pub dir: PathBuf,
+}
+
+/// 64 bit FNV-1a. Unlike the std hashers it is stable across Rust versions.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl BakeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BakeCache { dir: dir.into() }
+    }
+
+    /// Identifies a bake by the species, its generator version, the resolution,
+    /// the settings and the palette.
+    pub fn key<G: VegetationGenerator>(
+        settings: &G::Settings,
+        palette: Option<&BakePalette>,
+    ) -> String {
+        let size = settings.size();
+        let description = format!(
+            "{}:{}:{}x{}:{}:{:?}",
+            G::NAME,
+            G::VERSION,
+            size.x,
+            size.y,
+            settings_ron(settings.as_reflect()),
+            palette.map(|p| &p.0)
+        );
+        format!("{}_{:016x}", G::NAME, fnv1a(description.as_bytes()))
+    }
+
+    fn path(&self, key: &str, map: &str) -> PathBuf {
+        self.dir.join(format!("{}_{}.png", key, map))
+    }
+
+    /// The maps every bake of `G` has. Species with a height pass also derive
+    /// their normal and thickness maps from it, if they have any.
+    fn expected_maps<G: VegetationGenerator>() -> &'static [&'static str] {
+        if G::height_palette().is_empty() {
+            &["color"]
+        } else {
+            &["color", "height"]
+        }
+    }
+
+    /// Loads the maps of a bake of `G`. Returns `None` if any of the maps every
+    /// bake of `G` has isn't cached, see [`BakeCache::store`].
+    pub fn load<G: VegetationGenerator>(&self, key: &str) -> Option<Vec<(&'static str, Image)>> {
+        let expected = Self::expected_maps::<G>();
+        let mut maps = vec![];
+        for map in MAPS {
+            let Ok(img) = image::open(self.path(key, map)) else {
+                if expected.contains(&map) {
+                    return None;
+                }
+                continue;
+            };
+            let img = img.to_rgba8();
+            let format = if map == "color" {
+                TextureFormat::Rgba8UnormSrgb
+            } else {
+                TextureFormat::Rgba8Unorm
+            };
+            maps.push((
+                map,
+                Image::new(
+                    Extent3d {
+                        width: img.width(),
+                        height: img.height(),
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    img.into_raw(),
+                    format,
+                    RenderAssetUsages::all(),
+                ),
+            ));
+        }
+        Some(maps)
+    }
+
+    /// Writes the maps of a bake. The height map is written last, so a bake
+    /// interrupted while it is stored isn't loaded partially.
+    pub fn store(&self, key: &str, maps: &[(&str, &Image)]) -> Result<(), ImageError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let (height, others): (Vec<_>, Vec<_>) = maps.iter().partition(|(map, _)| *map == "height");
+        for (map, img) in others.into_iter().chain(height) {
+            export_image(img, &self.path(key, map), ExportFormat::Png)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::{Fern, Stalk},
+        test_images::filled,
+    };
+
+    #[test]
+    fn partial_bakes_are_misses() {
+        let cache = BakeCache::new(std::env::temp_dir().join("bevy_procedural_vegetation_cache"));
+        let color = filled(UVec2::ONE, TextureFormat::Rgba8UnormSrgb, [128; 4]);
+        let height = filled(UVec2::ONE, TextureFormat::Rgba8Unorm, [128; 4]);
+
+        cache.store("partial", &[("color", &color)]).unwrap();
+        assert!(cache.load::<Fern>("partial").is_none());
+        // species without a height pass only have a colour map
+        assert_eq!(
+            cache.load::<Stalk>("partial").map(|maps| maps.len()),
+            Some(1)
+        );
+
+        cache
+            .store("complete", &[("height", &height), ("color", &color)])
+            .unwrap();
+        let maps = cache.load::<Fern>("complete").unwrap();
+        assert_eq!(
+            maps.iter().map(|(map, _)| *map).collect::<Vec<_>>(),
+            ["color", "height"]
+        );
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::generator::{BakeTarget, VegetationSettings};
+
+/// Conifers. The baked texture is the needle cluster on the branch cards.
+#[derive(Component)]
+pub struct Conifer;
+
+#[derive(Reflect, Component, InspectorOptions, Clone)]
+#[reflect(Component, InspectorOptions)]
+pub struct ConiferSettings {
+    #[inspector(min = 0.1, max = 100.0, speed = 0.01)]
+    pub trunk_height: f32,
+    #[inspector(min = 0.001, max = 5.0, speed = 0.001)]
+    pub trunk_radius: f32,
+    /// Radius at the top relative to the radius at the base.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub trunk_taper: f32,
+
+    #[inspector(min = 1, max = 100)]
+    pub tiers: u32,
+    /// Height of the lowest tier relative to the trunk height.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub tier_start: f32,
+    #[inspector(min = 1, max = 32)]
+    pub branches_per_tier: u32,
+    #[inspector(min = 0.0, max = 50.0, speed = 0.01)]
+    pub branch_len: f32,
+    /// Exponent of the branch length falloff towards the top.
+    #[inspector(min = 0.0, max = 4.0, speed = 0.001)]
+    pub branch_falloff: f32,
+    #[inspector(min = -1.0, max = 1.0, speed = 0.001)]
+    pub branch_droop: f32,
+
+    #[inspector(min = 0, max = 200)]
+    pub twigs: u32,
+    #[inspector(min = 0, max = 200)]
+    pub needles: u32,
+    #[inspector(min = 0.0, max = 0.5, speed = 0.001)]
+    pub needle_len: f32,
+    #[inspector(min = 0.0, max = 0.05, speed = 0.0001)]
+    pub needle_w: f32,
+    #[inspector(min = 0.0, max = 1.5, speed = 0.001)]
+    pub needle_angle: f32,
+
+    /// Chance that a branch carries a cone near its tip.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub cone_chance: f32,
+    pub seed: u32,
+
+    pub target: BakeTarget,
+    // To enable automatic reloading
+    pub version: u32,
+}
+
+impl Default for ConiferSettings {
+    fn default() -> Self {
+        ConiferSettings {
+            trunk_height: 8.0,
+            trunk_radius: 0.15,
+            trunk_taper: 0.1,
+
+            tiers: 14,
+            tier_start: 0.15,
+            branches_per_tier: 6,
+            branch_len: 2.0,
+            branch_falloff: 1.2,
+            branch_droop: 0.2,
+
+            twigs: 12,
+            needles: 40,
+            needle_len: 0.08,
+            needle_w: 0.004,
+            needle_angle: 0.7,
+
+            cone_chance: 0.3,
+            seed: 0,
+
+            target: BakeTarget::default(),
+            version: 0,
+        }
+    }
+}
+
+impl VegetationSettings for ConiferSettings {
+    fn target(&self) -> &BakeTarget {
+        &self.target
+    }
+
+    fn target_mut(&mut self) -> &mut BakeTarget {
+        &mut self.target
+    }
+}
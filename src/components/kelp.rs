@@ -0,0 +1,161 @@
+use bevy::{
+    pbr::{MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+    },
+};
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::generator::{BakeTarget, VegetationSettings};
+
+/// Material extension for kelp. Instead of wind, the blades are lifted by their
+/// buoyancy and swayed by the water current.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct KelpMaterial {
+    #[uniform(100)]
+    pub time: f32,
+    /// Water depth at the holdfast. Deeper water dampens the surge.
+    #[uniform(100)]
+    pub depth: f32,
+    /// Direction and strength of the current in the xz-plane.
+    #[uniform(100)]
+    pub current: Vec2,
+    /// How strongly the blades are pulled towards the surface.
+    #[uniform(100)]
+    pub buoyancy: f32,
+    /// Number of fronds of the macro mesh, see [`KelpSettings::fronds`].
+    #[uniform(100)]
+    pub fronds: u32,
+    /// Width of a frond relative to its length of 4.
+    #[uniform(100)]
+    pub frond_width: f32,
+}
+
+impl Default for KelpMaterial {
+    fn default() -> Self {
+        KelpMaterial {
+            time: 0.0,
+            depth: 10.0,
+            current: Vec2::new(0.3, 0.0),
+            buoyancy: 1.0,
+            fronds: 16,
+            frond_width: 0.25,
+        }
+    }
+}
+
+impl MaterialExtension for KelpMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/kelp.wgsl".into()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        "shaders/kelp_prepass.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // disable backface culling
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+/// Kelp, seaweed and other algae.
+#[derive(Component)]
+pub struct Kelp;
+
+#[derive(Reflect, Component, InspectorOptions, Clone)]
+#[reflect(Component, InspectorOptions)]
+pub struct KelpSettings {
+    #[inspector(min = 0.001, max = 0.3, speed = 0.001)]
+    pub stipe_w: f32,
+
+    #[inspector(min = 1, max = 100)]
+    pub blades: u32,
+    #[inspector(min = 0.0, max = 2.0, speed = 0.001)]
+    pub blade_len: f32,
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub blade_w: f32,
+    #[inspector(min = 0.0, max = 1.5, speed = 0.001)]
+    pub blade_angle: f32,
+    #[inspector(min = 0, max = 100)]
+    pub ruffles: u32,
+    #[inspector(min = 0.0, max = 0.1, speed = 0.0001)]
+    pub ruffle_amp: f32,
+
+    #[inspector(min = 0.0, max = 0.1, speed = 0.0001)]
+    pub bladder_size: f32,
+
+    #[inspector(min = 1, max = 32)]
+    pub holdfast_roots: u32,
+    #[inspector(min = 0.0, max = 0.5, speed = 0.001)]
+    pub holdfast_len: f32,
+
+    /// Fronds of the macro mesh, each showing the baked kelp.
+    #[inspector(min = 1, max = 64)]
+    pub fronds: u32,
+    #[inspector(min = 0.01, max = 2.0, speed = 0.001)]
+    pub frond_w: f32,
+
+    /// Water depth at the holdfast, see [`KelpMaterial::depth`].
+    #[inspector(min = 0.0, max = 100.0, speed = 0.1)]
+    pub depth: f32,
+    /// Direction and strength of the current, see [`KelpMaterial::current`].
+    pub current: Vec2,
+    #[inspector(min = 0.0, max = 5.0, speed = 0.01)]
+    pub buoyancy: f32,
+
+    pub target: BakeTarget,
+    // To enable automatic reloading
+    pub version: u32,
+}
+
+impl Default for KelpSettings {
+    fn default() -> Self {
+        KelpSettings {
+            stipe_w: 0.02,
+
+            blades: 24,
+            blade_len: 0.35,
+            blade_w: 0.06,
+            blade_angle: 0.5,
+            ruffles: 12,
+            ruffle_amp: 0.004,
+
+            bladder_size: 0.012,
+
+            holdfast_roots: 7,
+            holdfast_len: 0.06,
+
+            fronds: 16,
+            frond_w: 0.25,
+
+            depth: 10.0,
+            current: Vec2::new(0.3, 0.0),
+            buoyancy: 1.0,
+
+            target: BakeTarget::default(),
+            version: 0,
+        }
+    }
+}
+
+impl VegetationSettings for KelpSettings {
+    fn target(&self) -> &BakeTarget {
+        &self.target
+    }
+
+    fn target_mut(&mut self) -> &mut BakeTarget {
+        &mut self.target
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::{
+    generator::{BakeTarget, VegetationSettings},
+    shapes::LeafShape,
+};
+
+/// A single leaf on a card.
+#[derive(Component)]
+pub struct Leaf;
+
+/// Settings for a single leaf with petiole and venation.
+#[derive(Reflect, Component, InspectorOptions, Clone)]
+#[reflect(Component, InspectorOptions)]
+pub struct LeafSettings {
+    pub shape: LeafShape,
+    #[inspector(min = 0.0, max = 2.0, speed = 0.001)]
+    pub blade_w: f32,
+
+    /// Length of the petiole relative to the whole leaf.
+    #[inspector(min = 0.0, max = 0.9, speed = 0.001)]
+    pub petiole_len: f32,
+    #[inspector(min = 0.0, max = 0.1, speed = 0.0001)]
+    pub petiole_w: f32,
+
+    #[inspector(min = 0.0, max = 0.1, speed = 0.0001)]
+    pub midrib_w: f32,
+
+    #[inspector(min = 0, max = 64)]
+    pub secondary: u32,
+    #[inspector(min = 0.0, max = 1.5, speed = 0.001)]
+    pub secondary_angle: f32,
+    /// How much the secondary veins bend towards the tip.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub secondary_curve: f32,
+    #[inspector(min = 0.0, max = 0.05, speed = 0.0001)]
+    pub secondary_w: f32,
+    /// Offset of the veins on one side. 0 gives opposite, 0.5 alternate veins.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub alternate: f32,
+
+    /// Number of tertiary veins between two neighbouring secondary veins.
+    #[inspector(min = 0, max = 32)]
+    pub tertiary: u32,
+    #[inspector(min = 0.0, max = 0.05, speed = 0.0001)]
+    pub tertiary_w: f32,
+
+    pub target: BakeTarget,
+    // To enable automatic reloading
+    pub version: u32,
+}
+
+impl Default for LeafSettings {
+    fn default() -> Self {
+        LeafSettings {
+            shape: LeafShape::Ovate,
+            blade_w: 0.9,
+
+            petiole_len: 0.15,
+            petiole_w: 0.02,
+
+            midrib_w: 0.012,
+
+            secondary: 8,
+            secondary_angle: 0.8,
+            secondary_curve: 0.5,
+            secondary_w: 0.005,
+            alternate: 0.3,
+
+            tertiary: 4,
+            tertiary_w: 0.002,
+
+            target: BakeTarget::default(),
+            version: 0,
+        }
+    }
+}
+
+impl VegetationSettings for LeafSettings {
+    fn target(&self) -> &BakeTarget {
+        &self.target
+    }
+
+    fn target_mut(&mut self) -> &mut BakeTarget {
+        &mut self.target
+    }
+}
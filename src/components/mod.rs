@@ -10,15 +10,74 @@ use bevy::{
     },
 };
 use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::generator::{BakeTarget, VegetationSettings};
+mod conifer;
+mod kelp;
+mod leaf;
 mod plugin;
 mod setup;
-pub use plugin::VegetationPlugin;
-pub use setup::{make_fern_material, make_fern_mesh, render_texture};
+mod stalk;
+pub use conifer::{Conifer, ConiferSettings};
+pub use kelp::{Kelp, KelpMaterial, KelpSettings};
+pub use leaf::{Leaf, LeafSettings};
+pub use plugin::{
+    BakeCompleted, BakeFailed, BakeQueue, BakeRequested, BakeTask, MacroMesh, RenderLayerAllocator,
+    VegetationPlugin,
+};
+pub use setup::{
+    make_card_material, make_card_mesh, make_fern_material, make_fern_mesh, make_fern_variant_mesh,
+    make_kelp_material, make_kelp_mesh, make_stalk_mesh, render_texture, BakeSetup,
+};
+pub use stalk::{Stalk, StalkSettings};
 
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct FernMaterial {
     #[uniform(100)]
     pub time: f32,
+    /// Regions of an atlas as (offset, size) in uv space. The variant of an
+    /// instance is selected by its mesh, see [`make_fern_variant_mesh`].
+    #[uniform(100)]
+    pub uv_rects: [Vec4; MAX_ATLAS_VARIANTS],
+    /// How much light shines through back-lit leaves.
+    #[uniform(100)]
+    pub translucency: f32,
+    /// How much the fronds hang down, see [`crate::season::SeasonState`].
+    #[uniform(100)]
+    pub droop: f32,
+    /// How much the leaves roll up.
+    #[uniform(100)]
+    pub curl: f32,
+    /// Whether the alpha of the colour map is a signed distance field.
+    #[uniform(100)]
+    pub sdf: u32,
+    /// Renders normals (1), depths (2) or the albedo (3) instead of the lit fern
+    /// for impostor captures.
+    #[uniform(100)]
+    pub capture: u32,
+    /// The baked mask map: thin texels (red) transmit more light, the green
+    /// channel holds the ambient occlusion.
+    #[texture(101)]
+    #[sampler(102)]
+    pub thickness: Option<Handle<Image>>,
+}
+
+/// How many variants of a [`FernMaterial`] can share one atlas.
+pub const MAX_ATLAS_VARIANTS: usize = 16;
+
+impl Default for FernMaterial {
+    fn default() -> Self {
+        FernMaterial {
+            time: 0.0,
+            uv_rects: [Vec4::new(0.0, 0.0, 1.0, 1.0); MAX_ATLAS_VARIANTS],
+            translucency: 0.0,
+            droop: 0.0,
+            curl: 0.0,
+            sdf: 0,
+            capture: 0,
+            thickness: None,
+        }
+    }
 }
 
 #[derive(Component)]
@@ -33,6 +92,10 @@ impl MaterialExtension for FernMaterial {
         "shaders/fern_prepass.wgsl".into()
     }
 
+    fn fragment_shader() -> ShaderRef {
+        "shaders/fern_fragment.wgsl".into()
+    }
+
     fn specialize(
         _pipeline: &MaterialExtensionPipeline,
         descriptor: &mut RenderPipelineDescriptor,
@@ -45,7 +108,7 @@ impl MaterialExtension for FernMaterial {
     }
 }
 
-#[derive(Reflect, Component, InspectorOptions)]
+#[derive(Reflect, Component, InspectorOptions, Clone)]
 #[reflect(Component, InspectorOptions)]
 pub struct FernSettings {
     #[inspector(min = 0.001, max = 0.3, speed = 0.001)]
@@ -73,13 +136,36 @@ pub struct FernSettings {
     #[inspector(min = 0.0, max = 10.0, speed = 0.00001)]
     pub l0: f32,
 
-    #[inspector(min = 8, max = 4096)]
-    pub width: u32,
-    #[inspector(min = 8, max = 4096)]
-    pub height: u32,
+    /// Strength of the baked normal map.
+    #[inspector(min = 0.0, max = 100.0, speed = 0.01)]
+    pub normal_strength: f32,
+
+    /// How much light shines through back-lit leaves.
+    #[inspector(min = 0.0, max = 4.0, speed = 0.01)]
+    pub translucency: f32,
+
+    /// Bake the silhouette as a signed distance field for crisp edges at any zoom.
+    pub sdf: bool,
+    /// Width of the signed distance field in texels.
+    #[inspector(min = 1.0, max = 64.0, speed = 0.1)]
+    pub sdf_spread: f32,
+
+    /// Time of the year: 0 spring, 0.25 summer, 0.5 autumn, 0.75 winter.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub season: f32,
+    /// Age of the plant. Old plants droop and lose leaflets.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub age: f32,
+
+    /// How much higher parts darken the parts around them in the bake.
+    #[inspector(min = 0.0, max = 100.0, speed = 0.01)]
+    pub ao_strength: f32,
 
-    pub meshes: Vec<AssetId<Mesh>>,
-    pub render_target: Option<Handle<Image>>,
+    /// How far the colours bleed into the transparent texels around the leaflets.
+    #[inspector(min = 0, max = 64)]
+    pub dilation: u32,
+
+    pub target: BakeTarget,
     // To enable automatic reloading
     pub version: u32,
 }
@@ -100,14 +186,33 @@ impl Default for FernSettings {
             stomp: 1.4,
             l0: 0.0521,
 
-            width: 512,
-            height: 512,
-            meshes: vec![],
-            render_target: None,
+            normal_strength: 8.0,
+            translucency: 0.8,
+            sdf: false,
+            sdf_spread: 8.0,
+            season: 0.25,
+            age: 0.3,
+            ao_strength: 4.0,
+
+            dilation: 8,
+            target: BakeTarget {
+                supersampling: 2,
+                ..default()
+            },
             version: 0,
         }
     }
 }
 
+impl VegetationSettings for FernSettings {
+    fn target(&self) -> &BakeTarget {
+        &self.target
+    }
+
+    fn target_mut(&mut self) -> &mut BakeTarget {
+        &mut self.target
+    }
+}
+
 #[derive(Component)]
 pub struct MainCamera;
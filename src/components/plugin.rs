@@ -1,105 +1,606 @@
-use super::{FernMaterial, FernSettings};
-use bevy::{pbr::ExtendedMaterial, prelude::*, render::view::NoFrustumCulling};
+use crate::{
+    bake::{downsample_image, generate_mips},
+    cache::BakeCache,
+    export::{export_bakes, Baked, ExportBake},
+    generator::{update_meshes, BakeFrame, VegetationGenerator, VegetationSettings},
+    palette::BakePalette,
+    raster::bake_from_color,
+};
+use bevy::{
+    ecs::{entity::Entities, system::SystemParam},
+    prelude::*,
+    render::{
+        render_resource::Extent3d,
+        view::{NoFrustumCulling, RenderLayers},
+    },
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
 use render_to_texture::{RenderToTexturePlugin, RenderToTextureTasks};
+use std::{collections::VecDeque, marker::PhantomData};
 
-use super::{make_fern_material, setup::make_fern_mesh};
+/// Generates, bakes and places the plants of the species `G`.
+pub struct VegetationPlugin<G: VegetationGenerator>(PhantomData<G>);
 
-pub struct VegetationPlugin;
+impl<G: VegetationGenerator> Default for VegetationPlugin<G> {
+    fn default() -> Self {
+        VegetationPlugin(PhantomData)
+    }
+}
 
-impl Plugin for VegetationPlugin {
+impl<G: VegetationGenerator> Plugin for VegetationPlugin<G> {
     fn build(&self, app: &mut App) {
         // app.add_systems(Startup, make_fern_material);
-        app.add_plugins(RenderToTexturePlugin)
-            .add_systems(Startup, create_tasks)
-            .add_systems(Update, (wait_for_texture, listen_for_changes));
+        if !app.is_plugin_added::<RenderToTexturePlugin>() {
+            app.add_plugins(RenderToTexturePlugin);
+        }
+        // shared by all species
+        if !app.world.contains_resource::<BakeQueue>() {
+            app.init_resource::<BakeQueue>()
+                .init_resource::<RenderLayerAllocator>()
+                .add_systems(Update, (start_bakes, free_render_layers));
+        }
+        app.register_type::<BakeTask>()
+            .register_type::<BakePalette>()
+            .add_event::<ExportBake<G>>()
+            .add_event::<BakeRequested<G>>()
+            .add_event::<BakeCompleted<G>>()
+            .add_event::<BakeFailed<G>>()
+            .add_systems(
+                Update,
+                (
+                    update_meshes::<G>,
+                    wait_for_texture::<G>,
+                    finish_bakes::<G>,
+                    listen_for_changes::<G>,
+                    finish_cache_lookups::<G>,
+                    export_bakes::<G>,
+                ),
+            );
     }
 }
 
-pub fn listen_for_changes(
-    query: Query<&FernSettings, Changed<FernSettings>>,
-    mut render_to_texture_tasks: ResMut<RenderToTextureTasks>,
+/// Hands out the render layers of the bakes so their scenes and previews don't
+/// overlap. Layer 0 is reserved for the main scene and layer 1 for the bake
+/// being rendered: `render_to_texture` only captures layer 1, so the parts of a
+/// bake are moved there while it renders, see [`BakeQueue`].
+#[derive(Resource, Debug)]
+pub struct RenderLayerAllocator {
+    used: u32,
+    owners: HashMap<Entity, LayerOwner>,
+}
+
+/// The layers of an owner and the entities spawned for it.
+#[derive(Debug, Default)]
+struct LayerOwner {
+    layer: u8,
+    count: u8,
+    entities: Vec<Entity>,
+}
+
+impl Default for RenderLayerAllocator {
+    fn default() -> Self {
+        RenderLayerAllocator {
+            used: 0b11,
+            owners: HashMap::new(),
+        }
+    }
+}
+
+impl RenderLayerAllocator {
+    /// Reserves `count` consecutive layers. Returns the first one or `None` if
+    /// there aren't enough free layers.
+    pub fn allocate(&mut self, count: u8) -> Option<u8> {
+        let total = RenderLayers::TOTAL_LAYERS as u8;
+        if count == 0 || count > total {
+            return None;
+        }
+        let mask = ((1u64 << count) - 1) as u32;
+        let layer = (0..=total - count).find(|layer| self.used & (mask << layer) == 0)?;
+        self.used |= mask << layer;
+        Some(layer)
+    }
+
+    /// Releases layers reserved by [`RenderLayerAllocator::allocate`].
+    pub fn free(&mut self, layer: u8, count: u8) {
+        let mask = ((1u64 << count.min(32)) - 1) as u32;
+        self.used &= !(mask << layer);
+    }
+
+    /// Frees the layers together with the bake of `owner` once it is despawned.
+    pub fn assign(&mut self, owner: Entity, layer: u8, count: u8) {
+        let owner = self.owners.entry(owner).or_default();
+        owner.layer = layer;
+        owner.count = count;
+    }
+
+    /// Despawns `entity` together with `owner`. For entities outside of the
+    /// hierarchy of the owner, e.g., the camera of a preview.
+    pub fn track(&mut self, owner: Entity, entity: Entity) {
+        self.owners.entry(owner).or_default().entities.push(entity);
+    }
+}
+
+/// Frees the render layers of despawned bakes and despawns the entities tracked
+/// for them. Their bakes are dropped by [`start_bakes`].
+pub fn free_render_layers(
+    mut removed: RemovedComponents<BakeTask>,
+    mut allocator: ResMut<RenderLayerAllocator>,
+    mut commands: Commands,
+    entities: &Entities,
+) {
+    for owner in removed.read() {
+        if entities.contains(owner) {
+            continue;
+        }
+        let Some(LayerOwner {
+            layer,
+            count,
+            entities,
+        }) = allocator.owners.remove(&owner)
+        else {
+            continue;
+        };
+        allocator.free(layer, count);
+        for entity in entities {
+            if let Some(entity) = commands.get_entity(entity) {
+                entity.despawn_recursive();
+            }
+        }
+    }
+}
+
+/// The bake task of a settings entity. Every bake is rendered at the current
+/// size and supersampling of the settings, see [`BakeQueue`].
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct BakeTask {
+    /// Unique name of the task. Also used when exporting the bake.
+    pub name: String,
+    /// The render layer the parts are drawn on, see [`RenderLayerAllocator`].
+    pub layer: u8,
+}
+
+impl BakeTask {
+    pub fn new(name: &str, layer: u8) -> Self {
+        BakeTask {
+            name: name.to_string(),
+            layer,
+        }
+    }
+}
+
+/// Sent when the settings entity `entity` starts baking, either by rendering
+/// or by loading the bake from the [`BakeCache`].
+#[derive(Event)]
+pub struct BakeRequested<G: VegetationGenerator> {
+    pub entity: Entity,
+    _marker: PhantomData<G>,
+}
+
+/// Sent when the bake of `entity` is shown. The handles are the ones of the
+/// [`Baked`] component.
+#[derive(Event)]
+pub struct BakeCompleted<G: VegetationGenerator> {
+    pub entity: Entity,
+    pub color: Handle<Image>,
+    pub normal: Option<Handle<Image>>,
+    pub height: Option<Handle<Image>>,
+    pub thickness: Option<Handle<Image>>,
+    pub frame: BakeFrame,
+    _marker: PhantomData<G>,
+}
+
+/// Sent when the bake of `entity` can't be completed or stored in the
+/// [`BakeCache`]. In the latter case the bake is still shown.
+#[derive(Event)]
+pub struct BakeFailed<G: VegetationGenerator> {
+    pub entity: Entity,
+    pub reason: String,
+    _marker: PhantomData<G>,
+}
+
+impl<G: VegetationGenerator> BakeFailed<G> {
+    pub fn new(entity: Entity, reason: impl Into<String>) -> Self {
+        BakeFailed {
+            entity,
+            reason: reason.into(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A bake waiting for its turn in the [`BakeQueue`].
+#[derive(Debug, Clone, PartialEq)]
+struct QueuedBake {
+    entity: Entity,
+    name: String,
+    layer: u8,
+    /// The size of the rendered image, i.e., including the supersampling.
+    size: UVec2,
+    /// The rendered image is filtered down by this factor when it arrives.
+    supersampling: u32,
+}
+
+/// The bakes of all species waiting to be rendered. `render_to_texture` can
+/// only render one task at a time and hands the result to whichever task is
+/// rendering, so a bake starts once the previous one is read back and its task
+/// is gone. Every bake gets a fresh task at its current size.
+#[derive(Resource, Default, Debug)]
+pub struct BakeQueue {
+    pending: VecDeque<QueuedBake>,
+    active: Option<QueuedBake>,
+    /// Whether the result of the active bake was read back.
+    done: bool,
+}
+
+impl BakeQueue {
+    /// Queues a bake of `entity`, replacing a bake of it that hasn't started yet.
+    fn push(&mut self, bake: QueuedBake) {
+        match self.pending.iter_mut().find(|b| b.entity == bake.entity) {
+            Some(queued) => *queued = bake,
+            None => self.pending.push_back(bake),
+        }
+    }
+
+    /// Drops the bake of `entity` that hasn't started yet.
+    fn cancel(&mut self, entity: Entity) {
+        self.pending.retain(|bake| bake.entity != entity);
+    }
+
+    /// The active bake of `entity` if it is still being rendered.
+    fn rendering(&self, entity: Entity) -> Option<&QueuedBake> {
+        self.active
+            .as_ref()
+            .filter(|bake| !self.done && bake.entity == entity)
+    }
+
+    /// Whether there are bakes that didn't finish yet.
+    pub fn is_busy(&self) -> bool {
+        self.active.is_some() || !self.pending.is_empty()
+    }
+}
+
+/// Moves the parts of `entity` to the layer the tasks of `render_to_texture`
+/// capture, or back to their own `layer`.
+fn set_bake_layers(
+    commands: &mut Commands,
+    children: &Query<&Children>,
+    entity: Entity,
+    layer: u8,
+    rendering: bool,
 ) {
-    for _ in query.iter() {
-        render_to_texture_tasks.get_mut("fern").unwrap().rerender();
+    let layers = if rendering {
+        RenderLayers::from_layers(&[layer, 1])
+    } else {
+        RenderLayers::layer(layer)
+    };
+    for part in std::iter::once(entity).chain(children.iter_descendants(entity)) {
+        if let Some(mut part) = commands.get_entity(part) {
+            part.insert(layers);
+        }
     }
 }
 
-pub fn create_tasks(
+/// Starts the next bake of the [`BakeQueue`] once the previous one is done.
+/// Bakes of despawned settings entities are dropped; a bake whose entity was
+/// despawned while rendering is read back and discarded.
+pub fn start_bakes(
+    mut queue: ResMut<BakeQueue>,
     mut render_to_texture_tasks: ResMut<RenderToTextureTasks>,
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
+    tasks: Query<(), With<BakeTask>>,
+    children: Query<&Children>,
 ) {
+    queue.pending.retain(|bake| tasks.contains(bake.entity));
+    if let Some(active) = queue.active.clone() {
+        if !queue.done && !tasks.contains(active.entity) {
+            if render_to_texture_tasks.image(&active.name, true).is_none() {
+                return;
+            }
+            if let Some(task) = render_to_texture_tasks.get_mut(&active.name) {
+                task.free(&mut commands);
+            }
+            queue.done = true;
+        }
+        if !queue.done || render_to_texture_tasks.get(&active.name).is_some() {
+            return;
+        }
+        queue.active = None;
+    }
+
+    let Some(bake) = queue.pending.pop_front() else {
+        return;
+    };
     render_to_texture_tasks.add(
-        "fern".to_string(),
-        2048,
-        512,
+        bake.name.clone(),
+        bake.size.x,
+        bake.size.y,
         false,
         &mut commands,
         &mut images,
         true,
     );
+    set_bake_layers(&mut commands, &children, bake.entity, bake.layer, true);
+    queue.active = Some(bake);
+    queue.done = false;
+}
+
+/// Queues the bakes of new settings entities and rebakes them whenever the
+/// settings change. Bakes found in the [`BakeCache`] are loaded instead.
+pub fn listen_for_changes<G: VegetationGenerator>(
+    query: Query<
+        (Entity, &G::Settings, &BakeTask, Option<&BakePalette>),
+        Or<(Changed<G::Settings>, Changed<BakePalette>)>,
+    >,
+    mut queue: ResMut<BakeQueue>,
+    mut images: ResMut<Assets<Image>>,
+    mut commands: Commands,
+    cache: Option<Res<BakeCache>>,
+    mut requested: EventWriter<BakeRequested<G>>,
+    mut failed: EventWriter<BakeFailed<G>>,
+) {
+    for (entity, settings, task, palette) in query.iter() {
+        let size = settings.size();
+        if size.x == 0 || size.y == 0 {
+            failed.send(BakeFailed::new(
+                entity,
+                format!("{} has an empty bake size", task.name),
+            ));
+            continue;
+        }
+        requested.send(BakeRequested {
+            entity,
+            _marker: PhantomData,
+        });
+
+        if let Some(cache) = cache.as_deref() {
+            // the bake is only rendered if it isn't cached, see `finish_cache_lookups`
+            let cache = cache.clone();
+            let key = BakeCache::key::<G>(settings, palette);
+            let lookup = AsyncComputeTaskPool::get().spawn(async move { cache.load::<G>(&key) });
+            commands.entity(entity).insert(CacheLookup(lookup));
+            queue.cancel(entity);
+            continue;
+        }
+        queue_render(&mut queue, &mut images, entity, settings, task);
+    }
+}
+
+/// Queues rendering the bake of `entity` and resizes its preview to the size
+/// it is rendered at.
+fn queue_render<S: VegetationSettings>(
+    queue: &mut BakeQueue,
+    images: &mut Assets<Image>,
+    entity: Entity,
+    settings: &S,
+    task: &BakeTask,
+) {
+    let supersampling = settings.supersampling();
+    let size = settings.size() * supersampling;
+    // the preview shows the parts at the size they are rendered at
+    if let Some(preview) = settings
+        .target()
+        .render_target
+        .as_ref()
+        .and_then(|handle| images.get_mut(handle))
+    {
+        if preview.size() != size {
+            preview.resize(Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            });
+        }
+    }
+    queue.push(QueuedBake {
+        entity,
+        name: task.name.clone(),
+        layer: task.layer,
+        size,
+        supersampling,
+    });
 }
 
+/// Loads the maps of a bake from the [`BakeCache`] on the [`AsyncComputeTaskPool`].
+/// Replacing it with a newer lookup drops the older one.
 #[derive(Component)]
-struct FernMacroMesh;
+struct CacheLookup(Task<Option<Vec<(&'static str, Image)>>>);
 
-fn wait_for_texture(
+/// Shows the bakes found in the [`BakeCache`] and renders the others.
+fn finish_cache_lookups<G: VegetationGenerator>(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, FernMaterial>>>,
-    mut render_to_texture_tasks: ResMut<RenderToTextureTasks>,
+    mut queue: ResMut<BakeQueue>,
     mut images: ResMut<Assets<Image>>,
-    _query: Query<&FernSettings>,
-    mut macro_fern_query: Query<(Entity, &FernMacroMesh)>,
+    mut query: Query<(Entity, &G::Settings, &BakeTask, &mut CacheLookup)>,
 ) {
-    if let Some(img) = render_to_texture_tasks.image("fern", false) {
-        // TODO: don't recreate the mesh! Better just change the texture. But how?
-        /* for settings in query.iter() {
-            println!("Got the image");
-            *images
-                .get_mut(settings.target_image.clone().unwrap())
-                .unwrap() = image.clone();
-
-        }*/
-
-        // Save it to disk
-        /*{
-            let width = img.width();
-            let height = img.height();
-            let mut writer = std::io::BufWriter::new(std::fs::File::create("test.png").unwrap());
-            image::write_buffer_with_format(
-                &mut writer,
-                &img.data.clone(),
-                height,
-                width,
-                image::ColorType::Rgba8,
-                image::ImageFormat::Png,
-            )
-            .unwrap();
-        }*/
-
-        // remove old
-        for (entity, _) in macro_fern_query.iter_mut() {
-            commands.entity(entity).despawn();
+    for (entity, settings, task, mut lookup) in query.iter_mut() {
+        let Some(maps) = block_on(poll_once(&mut lookup.0)) else {
+            continue;
+        };
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.remove::<CacheLookup>();
+        match maps {
+            Some(maps) => {
+                entity_commands.insert(CachedBake(maps));
+            }
+            None => queue_render(&mut queue, &mut images, entity, settings, task),
         }
+    }
+}
+
+/// Maps loaded from the [`BakeCache`] waiting to be shown.
+#[derive(Component)]
+struct CachedBake(Vec<(&'static str, Image)>);
+
+/// The maps of a bake being derived and mipmapped on the [`AsyncComputeTaskPool`].
+#[derive(Component)]
+struct BakeJob(Task<BakeResult>);
+
+/// What a [`BakeJob`] produced.
+struct BakeResult {
+    /// The maps or why they couldn't be derived.
+    maps: Result<Vec<(&'static str, Image)>, String>,
+    /// Why the maps couldn't be stored in the [`BakeCache`].
+    cache_error: Option<String>,
+}
+
+/// Checks the maps derived from a render of `size` texels. A colour map without
+/// any coverage means the parts weren't rendered.
+fn check_maps(name: &str, size: UVec2, maps: &[(&'static str, Image)]) -> Result<(), String> {
+    if let Some((map, img)) = maps.iter().find(|(_, img)| img.size() != size) {
+        return Err(format!(
+            "The {} map of {} is {}x{} instead of {}x{}",
+            map,
+            name,
+            img.width(),
+            img.height(),
+            size.x,
+            size.y
+        ));
+    }
+    match maps.iter().find(|(map, _)| *map == "color") {
+        Some((_, color)) if color.data.chunks_exact(4).any(|p| p[3] > 0) => Ok(()),
+        Some(_) => Err(format!("{} rendered nothing", name)),
+        None => Err(format!("{} has no colour map", name)),
+    }
+}
+
+/// Generates the mip chains of the maps. Unless the alpha is a signed distance
+/// field, the coverage of the colour map is kept at the alpha mask `threshold`
+/// of the materials. The height map is only exported, so it keeps a single level.
+fn with_mips(
+    maps: Vec<(&'static str, Image)>,
+    threshold: Option<f32>,
+) -> Vec<(&'static str, Image)> {
+    maps.into_iter()
+        .map(|(map, img)| {
+            let img = match map {
+                "color" => generate_mips(&img, threshold),
+                "height" => img,
+                _ => generate_mips(&img, None),
+            };
+            (map, img)
+        })
+        .collect()
+}
+
+/// Marks the instances of the macro mesh of the species `G`.
+#[derive(Component)]
+pub struct MacroMesh<G: VegetationGenerator> {
+    /// The settings entity whose bake the instance shows.
+    pub source: Entity,
+    _marker: PhantomData<G>,
+}
 
-        let material = make_fern_material(Some(images.add(img)), None);
-        let mesh = make_fern_mesh();
-        let mesh_handle = meshes.add(mesh);
-        let material_handle = materials.add(material);
+/// Everything needed to show a finished bake.
+#[derive(SystemParam)]
+struct BakeOutput<'w, 's, G: VegetationGenerator> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<<G as VegetationGenerator>::Material>>,
+    images: ResMut<'w, Assets<Image>>,
+    macro_query: Query<
+        'w,
+        's,
+        (
+            &'static MacroMesh<G>,
+            &'static Handle<Mesh>,
+            &'static Handle<<G as VegetationGenerator>::Material>,
+        ),
+    >,
+    baked_query: Query<'w, 's, &'static Baked<G>>,
+    completed: EventWriter<'w, BakeCompleted<G>>,
+    failed: EventWriter<'w, BakeFailed<G>>,
+}
 
-        for i in 0..30 {
-            let s = (i as f32 * 100.0).sin() + 2.0;
+impl<'w, 's, G: VegetationGenerator> BakeOutput<'w, 's, G> {
+    /// Shows the new maps on the macro meshes of `source`. The images and mesh
+    /// of a previous bake are replaced in place and its material is updated, so
+    /// existing instances keep their transforms, components and material edits.
+    /// The maps already carry their mips.
+    fn show(&mut self, source: Entity, settings: &G::Settings, maps: Vec<(&'static str, Image)>) {
+        let previous: HashMap<&str, Handle<Image>> = self
+            .baked_query
+            .get(source)
+            .map(|baked| {
+                baked
+                    .maps()
+                    .into_iter()
+                    .map(|(map, handle)| (map, handle.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
 
-            commands.spawn((
+        let mut handles: HashMap<&str, Handle<Image>> = HashMap::new();
+        for (map, img) in maps {
+            let handle = match previous.get(map) {
+                Some(handle) => {
+                    self.images.insert(handle, img);
+                    handle.clone()
+                }
+                None => self.images.add(img),
+            };
+            handles.insert(map, handle);
+        }
+        let color = handles.remove("color");
+        let normal = handles.remove("normal");
+        let thickness = handles.remove("thickness");
+        let Some(color) = color else {
+            self.failed
+                .send(BakeFailed::new(source, "The bake has no colour map"));
+            return;
+        };
+        let height = handles.remove("height");
+        let frame = G::frame(settings);
+        self.commands.entity(source).insert(Baked::<G>::new(
+            color.clone(),
+            normal.clone(),
+            height.clone(),
+            thickness.clone(),
+            frame,
+        ));
+        self.completed.send(BakeCompleted {
+            entity: source,
+            color: color.clone(),
+            normal: normal.clone(),
+            height,
+            thickness: thickness.clone(),
+            frame,
+            _marker: PhantomData,
+        });
+        if let Some((_, mesh_handle, material_handle)) = self
+            .macro_query
+            .iter()
+            .find(|(macro_mesh, _, _)| macro_mesh.source == source)
+        {
+            self.meshes.insert(mesh_handle, G::macro_mesh(settings));
+            if let Some(material) = self.materials.get_mut(material_handle) {
+                // the maps of the previous bake were replaced behind their handles
+                let (color_texture, normal_texture) = G::textures(material);
+                color_texture.get_or_insert(color);
+                if let Some(normal) = normal {
+                    normal_texture.get_or_insert(normal);
+                }
+                G::apply_settings(settings, material, thickness);
+            } else {
+                let mut material = G::material(Some(color), normal);
+                G::apply_settings(settings, &mut material, thickness);
+                self.materials.insert(material_handle, material);
+            }
+            return;
+        }
+
+        let mut material = G::material(Some(color), normal);
+        G::apply_settings(settings, &mut material, thickness);
+        let mesh_handle = self.meshes.add(G::macro_mesh(settings));
+        let material_handle = self.materials.add(material);
+        for transform in G::instances(settings) {
+            self.commands.spawn((
                 MaterialMeshBundle {
                     mesh: mesh_handle.clone(),
-                    transform: Transform::from_xyz(
-                        ((1012.0 * i as f32).sin() * 100000.0) % 10.0,
-                        s / 2.0,
-                        ((432.0 * i as f32).sin() * 100000.0) % 10.0,
-                    )
-                    .with_scale(Vec3::splat(s)),
+                    transform,
                     material: material_handle.clone(),
                     ..default()
                 },
@@ -111,8 +612,423 @@ fn wait_for_texture(
                 // We must disable the built-in frustum culling by adding the `NoFrustumCulling` marker
                 // component to avoid incorrect culling.
                 NoFrustumCulling,
-                FernMacroMesh,
+                MacroMesh::<G> {
+                    source,
+                    _marker: PhantomData,
+                },
             ));
         }
     }
 }
+
+/// Starts deriving the maps of finished renders and loaded bakes, see [`BakeJob`].
+/// The task of a render is freed as soon as it is read back.
+fn wait_for_texture<G: VegetationGenerator>(
+    mut commands: Commands,
+    mut render_to_texture_tasks: ResMut<RenderToTextureTasks>,
+    mut queue: ResMut<BakeQueue>,
+    children: Query<&Children>,
+    mut query: Query<(
+        Entity,
+        &G::Settings,
+        &BakeTask,
+        Option<&BakePalette>,
+        Option<&mut CachedBake>,
+        Has<CacheLookup>,
+    )>,
+    cache: Option<Res<BakeCache>>,
+) {
+    let pool = AsyncComputeTaskPool::get();
+    for (source, settings, task, palette, cached, looking_up) in query.iter_mut() {
+        // keep the coverage at the alpha mask threshold of the materials
+        let threshold = G::sdf_spread(settings).is_none().then_some(0.5);
+        if let Some(mut cached) = cached {
+            let maps = std::mem::take(&mut cached.0);
+            commands
+                .entity(source)
+                .remove::<CachedBake>()
+                .insert(BakeJob(pool.spawn(async move {
+                    BakeResult {
+                        maps: Ok(with_mips(maps, threshold)),
+                        cache_error: None,
+                    }
+                })));
+            continue;
+        }
+
+        let Some(bake) = queue.rendering(source).cloned() else {
+            continue;
+        };
+        let Some(img) = render_to_texture_tasks.image(&bake.name, true) else {
+            continue;
+        };
+        if let Some(task) = render_to_texture_tasks.get_mut(&bake.name) {
+            task.free(&mut commands);
+        }
+        set_bake_layers(&mut commands, &children, source, bake.layer, false);
+        queue.done = true;
+        // the settings changed while rendering, so the next bake replaces this one
+        if looking_up || queue.pending.iter().any(|bake| bake.entity == source) {
+            continue;
+        }
+        let settings = settings.clone();
+        let supersampling = bake.supersampling;
+        let name = task.name.clone();
+        let cache = cache
+            .as_deref()
+            .map(|cache| (cache.clone(), BakeCache::key::<G>(&settings, palette)));
+        commands
+            .entity(source)
+            .insert(BakeJob(pool.spawn(async move {
+                let color = match supersampling {
+                    1 => img,
+                    factor => downsample_image(&img, factor),
+                };
+                let maps = bake_from_color::<G>(&settings, color, supersampling);
+                if let Err(err) = check_maps(&name, settings.size(), &maps) {
+                    return BakeResult {
+                        maps: Err(err),
+                        cache_error: None,
+                    };
+                }
+                let cache_error = cache.and_then(|(cache, key)| {
+                    let refs: Vec<(&str, &Image)> =
+                        maps.iter().map(|(map, img)| (*map, img)).collect();
+                    let err = cache.store(&key, &refs).err()?;
+                    Some(format!("Failed to cache {}: {}", name, err))
+                });
+                BakeResult {
+                    maps: Ok(with_mips(maps, threshold)),
+                    cache_error,
+                }
+            })));
+    }
+}
+
+/// Shows the maps of finished [`BakeJob`]s.
+fn finish_bakes<G: VegetationGenerator>(
+    mut output: BakeOutput<G>,
+    mut query: Query<(Entity, &G::Settings, &mut BakeJob)>,
+) {
+    for (source, settings, mut job) in query.iter_mut() {
+        let Some(result) = block_on(poll_once(&mut job.0)) else {
+            continue;
+        };
+        output.commands.entity(source).remove::<BakeJob>();
+        if let Some(reason) = result.cache_error {
+            warn!("{}", reason);
+            output.failed.send(BakeFailed::new(source, reason));
+        }
+        match result.maps {
+            Ok(maps) => output.show(source, settings, maps),
+            Err(reason) => {
+                error!("{}", reason);
+                output.failed.send(BakeFailed::new(source, reason));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::{Fern, FernSettings},
+        generator::BakeTarget,
+        test_images::filled,
+    };
+    use bevy::{render::render_resource::TextureFormat, tasks::TaskPool};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .init_resource::<RenderToTextureTasks>()
+            .init_resource::<BakeQueue>()
+            .add_event::<BakeRequested<Fern>>()
+            .add_event::<BakeFailed<Fern>>()
+            .add_systems(Update, (listen_for_changes::<Fern>, start_bakes).chain());
+        app
+    }
+
+    fn spawn(app: &mut App, name: &str, layer: u8) -> Entity {
+        app.world
+            .spawn((
+                FernSettings {
+                    target: BakeTarget::new(64, 32),
+                    ..default()
+                },
+                BakeTask::new(name, layer),
+                RenderLayers::layer(layer),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn bakes_are_rendered_one_at_a_time() {
+        let mut app = app();
+        let a = spawn(&mut app, "a", 2);
+        let b = spawn(&mut app, "b", 3);
+        app.update();
+
+        let tasks = app.world.resource::<RenderToTextureTasks>();
+        assert_eq!(
+            tasks.get("a").map(|task| task.size()),
+            Some(UVec2::new(64, 32))
+        );
+        assert!(tasks.get("b").is_none());
+        let queue = app.world.resource::<BakeQueue>();
+        assert_eq!(queue.rendering(a).map(|bake| bake.layer), Some(2));
+        assert_eq!(queue.pending.len(), 1);
+        assert_eq!(
+            app.world.get::<RenderLayers>(a),
+            Some(&RenderLayers::from_layers(&[2, 1]))
+        );
+        assert_eq!(
+            app.world.get::<RenderLayers>(b),
+            Some(&RenderLayers::layer(3))
+        );
+
+        // the waiting bake is replaced and starts at the new size
+        app.world.get_mut::<FernSettings>(b).unwrap().target.width = 128;
+        app.update();
+        let queue = app.world.resource::<BakeQueue>();
+        assert_eq!(queue.pending.len(), 1);
+        assert_eq!(queue.pending[0].size, UVec2::new(128, 32));
+
+        // as it does with the supersampling, which is clamped
+        app.world
+            .get_mut::<FernSettings>(b)
+            .unwrap()
+            .target
+            .supersampling = 9;
+        app.update();
+        let queue = app.world.resource::<BakeQueue>();
+        assert_eq!(queue.pending.len(), 1);
+        assert_eq!(queue.pending[0].size, UVec2::new(512, 128));
+        assert_eq!(queue.pending[0].supersampling, 4);
+        assert!(app
+            .world
+            .resource::<RenderToTextureTasks>()
+            .get("b")
+            .is_none());
+    }
+
+    fn color(size: UVec2, alpha: u8) -> Image {
+        filled(size, TextureFormat::Rgba8UnormSrgb, [255, 255, 255, alpha])
+    }
+
+    #[test]
+    fn maps_are_checked() {
+        let size = UVec2::new(4, 2);
+        assert_eq!(
+            check_maps("a", size, &[("color", color(size, 255))]),
+            Ok(())
+        );
+        assert_eq!(
+            check_maps("a", size, &[("color", color(size, 0))]),
+            Err("a rendered nothing".to_string())
+        );
+        assert!(check_maps("a", size, &[("color", color(UVec2::new(2, 2), 255))]).is_err());
+        assert!(check_maps("a", size, &[]).is_err());
+    }
+
+    #[test]
+    fn failed_bakes_are_reported() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .init_asset::<Mesh>()
+            .init_asset::<<Fern as VegetationGenerator>::Material>()
+            .add_event::<BakeCompleted<Fern>>()
+            .add_event::<BakeFailed<Fern>>()
+            .add_systems(Update, finish_bakes::<Fern>);
+        let pool = AsyncComputeTaskPool::get();
+        let size = UVec2::new(4, 2);
+        let failed = app
+            .world
+            .spawn((
+                FernSettings::default(),
+                BakeJob(pool.spawn(async {
+                    BakeResult {
+                        maps: Err("a rendered nothing".to_string()),
+                        cache_error: None,
+                    }
+                })),
+            ))
+            .id();
+        let uncached = app
+            .world
+            .spawn((
+                FernSettings::default(),
+                BakeJob(pool.spawn(async move {
+                    BakeResult {
+                        maps: Ok(vec![("color", color(size, 255))]),
+                        cache_error: Some("Failed to cache b".to_string()),
+                    }
+                })),
+            ))
+            .id();
+        while app.world.query::<&BakeJob>().iter(&app.world).count() > 0 {
+            app.update();
+        }
+
+        let events = app.world.resource::<Events<BakeFailed<Fern>>>();
+        let mut reasons: Vec<_> = events
+            .get_reader()
+            .read(events)
+            .map(|e| (e.entity, e.reason.clone()))
+            .collect();
+        reasons.sort();
+        let mut expected = vec![
+            (failed, "a rendered nothing".to_string()),
+            (uncached, "Failed to cache b".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(reasons, expected);
+        // the bake that couldn't be cached is still shown
+        assert!(app.world.get::<Baked<Fern>>(uncached).is_some());
+        assert!(app.world.get::<Baked<Fern>>(failed).is_none());
+    }
+
+    #[test]
+    fn rebakes_keep_material_edits() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .init_asset::<Mesh>()
+            .init_asset::<<Fern as VegetationGenerator>::Material>()
+            .add_event::<BakeCompleted<Fern>>()
+            .add_event::<BakeFailed<Fern>>()
+            .add_systems(Update, finish_bakes::<Fern>);
+        let size = UVec2::new(4, 2);
+        let bake = |app: &mut App, entity: Entity| {
+            let job = AsyncComputeTaskPool::get().spawn(async move {
+                BakeResult {
+                    maps: Ok(vec![("color", color(size, 255))]),
+                    cache_error: None,
+                }
+            });
+            app.world.entity_mut(entity).insert(BakeJob(job));
+            while app.world.get::<BakeJob>(entity).is_some() {
+                app.update();
+            }
+        };
+        let source = app.world.spawn(FernSettings::default()).id();
+        bake(&mut app, source);
+
+        let handle = app
+            .world
+            .query::<&Handle<<Fern as VegetationGenerator>::Material>>()
+            .iter(&app.world)
+            .next()
+            .unwrap()
+            .clone();
+        let mut materials = app
+            .world
+            .resource_mut::<Assets<<Fern as VegetationGenerator>::Material>>();
+        let material = materials.get_mut(&handle).unwrap();
+        material.base.perceptual_roughness = 0.9;
+        let color_texture = material.base.base_color_texture.clone();
+        app.world
+            .get_mut::<FernSettings>(source)
+            .unwrap()
+            .translucency = 0.25;
+        bake(&mut app, source);
+
+        let materials = app
+            .world
+            .resource::<Assets<<Fern as VegetationGenerator>::Material>>();
+        assert_eq!(materials.len(), 1);
+        let material = materials.get(&handle).unwrap();
+        assert_eq!(material.base.perceptual_roughness, 0.9);
+        assert_eq!(material.base.base_color_texture, color_texture);
+        assert_eq!(material.extension.translucency, 0.25);
+    }
+
+    #[test]
+    fn layers_run_out() {
+        let mut allocator = RenderLayerAllocator::default();
+        assert_eq!(allocator.allocate(0), None);
+        assert_eq!(allocator.allocate(40), None);
+        assert_eq!(allocator.allocate(2), Some(2));
+        assert_eq!(allocator.allocate(28), Some(4));
+        assert_eq!(allocator.allocate(1), None);
+        allocator.free(2, 2);
+        assert_eq!(allocator.allocate(1), Some(2));
+    }
+
+    #[test]
+    fn despawned_bakes_free_their_layers_and_tracked_entities() {
+        let mut app = App::new();
+        app.init_resource::<RenderLayerAllocator>()
+            .add_systems(Update, free_render_layers);
+        let owner = app.world.spawn(BakeTask::new("a", 2)).id();
+        let camera = app.world.spawn_empty().id();
+        let other = app.world.spawn(RenderLayers::layer(2)).id();
+        let mut allocator = app.world.resource_mut::<RenderLayerAllocator>();
+        let layer = allocator.allocate(1).unwrap();
+        allocator.assign(owner, layer, 1);
+        allocator.track(owner, camera);
+
+        // removing the task alone keeps everything
+        app.world.entity_mut(owner).remove::<BakeTask>();
+        app.update();
+        assert!(app.world.get_entity(camera).is_some());
+
+        app.world.entity_mut(owner).insert(BakeTask::new("a", 2));
+        app.update();
+        app.world.despawn(owner);
+        app.update();
+        assert!(app.world.get_entity(camera).is_none());
+        assert!(app.world.get_entity(other).is_some());
+        let mut allocator = app.world.resource_mut::<RenderLayerAllocator>();
+        assert_eq!(allocator.allocate(1), Some(layer));
+    }
+
+    #[test]
+    fn cached_bakes_are_loaded_in_the_background() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let dir = std::env::temp_dir().join("bevy_procedural_vegetation_lookup");
+        let cache = BakeCache::new(&dir);
+        let mut app = app();
+        app.add_systems(Update, finish_cache_lookups::<Fern>)
+            .insert_resource(cache.clone());
+        let hit = spawn(&mut app, "hit", 2);
+        let miss = spawn(&mut app, "miss", 3);
+        app.world.get_mut::<FernSettings>(miss).unwrap().age = 0.9;
+        let size = UVec2::new(64, 32);
+        let (color, height) = (color(size, 255), color(size, 255));
+        let key = BakeCache::key::<Fern>(app.world.get::<FernSettings>(hit).unwrap(), None);
+        cache
+            .store(&key, &[("color", &color), ("height", &height)])
+            .unwrap();
+
+        app.update();
+        assert!(!app.world.resource::<BakeQueue>().is_busy());
+        while app.world.query::<&CacheLookup>().iter(&app.world).count() > 0 {
+            app.update();
+        }
+        app.update();
+        assert!(app.world.get::<CachedBake>(hit).is_some());
+        assert!(app.world.get::<CachedBake>(miss).is_none());
+        let queue = app.world.resource::<BakeQueue>();
+        assert_eq!(queue.rendering(miss).map(|bake| bake.layer), Some(3));
+        assert!(queue.pending.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bakes_of_despawned_entities_are_dropped() {
+        let mut app = app();
+        spawn(&mut app, "a", 2);
+        let b = spawn(&mut app, "b", 3);
+        app.update();
+
+        app.world.despawn(b);
+        app.update();
+        assert!(app.world.resource::<BakeQueue>().pending.is_empty());
+    }
+}
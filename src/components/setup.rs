@@ -1,101 +1,272 @@
-use super::{FernMaterial, FernSettings};
+use super::{BakeTask, FernMaterial, KelpMaterial, RenderLayerAllocator};
+use crate::{
+    generator::{VegetationGenerator, VegetationSettings},
+    palette::{BakePalette, Palette},
+};
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
-    render::{mesh::PrimitiveTopology, render_asset::RenderAssetUsages, view::RenderLayers},
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+        view::RenderLayers,
+    },
 };
 use render_to_texture::create_render_texture;
 
-pub fn render_texture(
+/// Everything [`render_texture`] needs to spawn a bake.
+#[derive(SystemParam)]
+pub struct BakeSetup<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    pub meshes: ResMut<'w, Assets<Mesh>>,
+    pub materials: ResMut<'w, Assets<ColorMaterial>>,
+    pub images: ResMut<'w, Assets<Image>>,
+    pub layers: ResMut<'w, RenderLayerAllocator>,
+}
+
+/// Bakes the parts of a plant into a texture. Every part is painted with the
+/// corresponding palette, see [`VegetationGenerator::palettes`]. The layer is
+/// taken from the [`RenderLayerAllocator`] and freed once the settings entity,
+/// which owns the bake task `name`, is despawned. The camera rendering the
+/// preview into the returned image is despawned with it. Returns `None` if
+/// there isn't a palette for every part or all layers are in use.
+pub fn render_texture<G: VegetationGenerator>(
+    setup: &mut BakeSetup,
+    name: &str,
     width: u32,
     height: u32,
-    commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<ColorMaterial>>,
-    images: &mut ResMut<Assets<Image>>,
-    colors: [Color; 3],
-    layer: u8,
-) -> Handle<Image> {
-    let mut settings = FernSettings {
-        width,
-        height,
-        ..default()
+    palettes: &[Palette],
+) -> Option<Handle<Image>> {
+    let parts = G::parts();
+    if palettes.len() < parts.len() {
+        error!(
+            "Cannot bake {}, {} palettes given for {} parts",
+            name,
+            palettes.len(),
+            parts.len()
+        );
+        return None;
+    }
+    let BakeSetup {
+        commands,
+        meshes,
+        materials,
+        images,
+        layers,
+    } = setup;
+
+    let Some(layer) = layers.allocate(1) else {
+        error!("Cannot bake {}, all render layers are in use", name);
+        return None;
     };
 
-    let (img, _) = create_render_texture(width, height, commands, images, layer, true);
-    let layer = RenderLayers::layer(layer);
-    let mesh = meshes.add(Mesh::from(Cuboid::new(1.0, 1.0, 1.0)));
-    let mesh2 = meshes.add(Mesh::from(Cuboid::new(1.0, 1.0, 1.0)));
-    let mesh3 = meshes.add(Mesh::from(Cuboid::new(1.0, 1.0, 1.0)));
-    settings.meshes = vec![mesh.id(), mesh2.id(), mesh3.id()];
-    settings.render_target = Some(img.clone());
+    let mut settings = G::Settings::default();
+    settings.set_size(UVec2::new(width, height));
+    let supersampling = settings.supersampling();
+    let (render_width, render_height) = (width * supersampling, height * supersampling);
+
+    let (img, preview) =
+        create_render_texture(render_width, render_height, commands, images, layer, true);
+    let handles: Vec<Handle<Mesh>> = parts
+        .iter()
+        .map(|_| meshes.add(Mesh::from(Cuboid::new(1.0, 1.0, 1.0))))
+        .collect();
+    settings.set_target(handles.iter().map(|mesh| mesh.id()).collect(), img.clone());
+    let depths: Vec<f32> = parts.iter().map(|(_, z)| *z).collect();
+
+    // the colours are painted into the vertices
+    let parent = spawn_parts(
+        commands,
+        materials,
+        &handles,
+        &depths,
+        &vec![Color::WHITE; handles.len()],
+        RenderLayers::layer(layer),
+    );
+    let task = BakeTask::new(name, layer);
+    commands.entity(parent).insert((
+        Name::new(name.to_string()),
+        settings,
+        task,
+        BakePalette(palettes.to_vec()),
+    ));
+    layers.assign(parent, layer, 1);
+    layers.track(parent, preview);
 
+    Some(img)
+}
+
+/// Spawns the 2d meshes of the parts on the given layer. Returns the entity of the first part.
+fn spawn_parts(
+    commands: &mut Commands,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    handles: &[Handle<Mesh>],
+    depths: &[f32],
+    colors: &[Color],
+    layer: RenderLayers,
+) -> Entity {
     commands
         .spawn((
             ColorMesh2dBundle {
-                mesh: mesh.into(),
+                mesh: handles[0].clone().into(),
                 material: materials.add(ColorMaterial::from(colors[0])),
                 ..default()
             },
             layer,
-            Name::new("fern"),
-            settings,
         ))
         .with_children(|parent| {
-            parent.spawn((
-                ColorMesh2dBundle {
-                    mesh: mesh2.clone().into(),
-                    material: materials.add(ColorMaterial::from(colors[1])),
-                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, -1.0)),
-                    ..default()
-                },
-                layer,
-            ));
-            parent.spawn((
-                ColorMesh2dBundle {
-                    mesh: mesh3.into(),
-                    material: materials.add(ColorMaterial::from(colors[2])),
-                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, -1.0)),
-                    ..default()
-                },
-                layer,
-            ));
-        });
-
-    return img;
+            for (i, handle) in handles.iter().enumerate().skip(1) {
+                parent.spawn((
+                    ColorMesh2dBundle {
+                        mesh: handle.clone().into(),
+                        material: materials.add(ColorMaterial::from(colors[i])),
+                        transform: Transform::from_translation(Vec3::new(0.0, 0.0, depths[i])),
+                        ..default()
+                    },
+                    layer,
+                ));
+            }
+        })
+        .id()
 }
 
 pub fn make_fern_mesh() -> Mesh {
+    make_fern_variant_mesh(0)
+}
+
+/// A fern mesh sampling the `variant`-th region of an atlas. The shader ignores
+/// the x coordinate of the vertices, so it is used to pass the variant.
+pub fn make_fern_variant_mesh(variant: u32) -> Mesh {
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::all());
     let count = 40 * 12;
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0., 0., 0.]].repeat(count));
-    // TODO: to enable color in PBR (used for ao). Is there a way without adding an attribute?
-    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1., 1., 1., 1.]].repeat(count));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.]].repeat(count));
     mesh.insert_attribute(
-        Mesh::ATTRIBUTE_TANGENT,
-        vec![[0., 0., 0., 0.]].repeat(count),
+        Mesh::ATTRIBUTE_POSITION,
+        [[variant as f32, 0., 0.]].repeat(count),
     );
-    return mesh;
+    // the occlusion is baked into the mask map, so no vertex colours are needed
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, [[0., 0.]].repeat(count));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, [[0., 0., 0., 0.]].repeat(count));
+    mesh
 }
 
 pub fn make_fern_material(
     fern_color: Option<Handle<Image>>,
     fern_normal: Option<Handle<Image>>,
 ) -> bevy::pbr::ExtendedMaterial<StandardMaterial, FernMaterial> {
-    let material = bevy::pbr::ExtendedMaterial::<StandardMaterial, FernMaterial> {
+    bevy::pbr::ExtendedMaterial::<StandardMaterial, FernMaterial> {
         base: StandardMaterial {
             //base_color: Color::rgb(0.5, 0.5, 0.4),
             base_color_texture: fern_color,
             normal_map_texture: fern_normal,
-            metallic: 0.4,
-            perceptual_roughness: 0.2,
-            reflectance: 0.0,
+            // leaves are dielectric; back-lit leaves are handled by the translucency
+            metallic: 0.0,
+            perceptual_roughness: 0.5,
+            reflectance: 0.2,
             double_sided: true,
             alpha_mode: AlphaMode::Mask(0.5),
             ..default()
         },
-        extension: FernMaterial { time: 0.0 },
-    };
+        extension: FernMaterial::default(),
+    }
+}
+
+/// A kelp mesh with `fronds` strips of 24 vertices each. The shader places the
+/// fronds, see [`KelpMaterial::fronds`].
+pub fn make_kelp_mesh(fronds: u32) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::all());
+    let count = fronds as usize * 24;
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, [[0., 0., 0.]].repeat(count));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, [[1., 1., 1., 1.]].repeat(count));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, [[0., 0.]].repeat(count));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, [[0., 0., 0., 0.]].repeat(count));
+    mesh
+}
+
+pub fn make_kelp_material(
+    kelp_color: Option<Handle<Image>>,
+    kelp_normal: Option<Handle<Image>>,
+    depth: f32,
+    current: Vec2,
+) -> bevy::pbr::ExtendedMaterial<StandardMaterial, KelpMaterial> {
+    bevy::pbr::ExtendedMaterial::<StandardMaterial, KelpMaterial> {
+        base: StandardMaterial {
+            base_color_texture: kelp_color,
+            normal_map_texture: kelp_normal,
+            metallic: 0.0,
+            perceptual_roughness: 0.4,
+            reflectance: 0.3,
+            double_sided: true,
+            alpha_mode: AlphaMode::Mask(0.5),
+            ..default()
+        },
+        extension: KelpMaterial {
+            depth,
+            current,
+            ..default()
+        },
+    }
+}
+
+/// Two crossed vertical cards showing the baked stalk texture. The stalk grows
+/// from the origin along the y-axis with unit length.
+pub fn make_stalk_mesh() -> Mesh {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut indices = Vec::new();
+    for (across, normal) in [(Vec3::X, Vec3::Z), (Vec3::Z, Vec3::X)] {
+        let i = positions.len() as u16;
+        // the texture spans twice the stalk length across the stalk
+        for (u, v) in [(1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)] {
+            let p = across * (v - 0.5) * 2.0 + Vec3::Y * (1.0 - u);
+            positions.push(p.to_array());
+            normals.push(normal.to_array());
+            uvs.push([u, v]);
+        }
+        indices.extend_from_slice(&[i, i + 1, i + 2, i, i + 2, i + 3]);
+    }
+
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U16(indices))
+}
+
+/// Alpha-masked, double-sided material for cards showing a baked texture.
+pub fn make_card_material(
+    color: Option<Handle<Image>>,
+    normal: Option<Handle<Image>>,
+) -> StandardMaterial {
+    StandardMaterial {
+        base_color_texture: color,
+        normal_map_texture: normal,
+        perceptual_roughness: 0.6,
+        reflectance: 0.2,
+        double_sided: true,
+        cull_mode: None,
+        alpha_mode: AlphaMode::Mask(0.5),
+        ..default()
+    }
+}
+
+/// A single vertical card showing the baked texture. The plant grows from the
+/// origin along the y-axis with unit length.
+pub fn make_card_mesh() -> Mesh {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    // the texture spans twice the length of the plant across it
+    for (u, v) in [(1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)] {
+        let p = Vec3::X * (v - 0.5) * 2.0 + Vec3::Y * (1.0 - u);
+        positions.push(p.to_array());
+        normals.push(Vec3::Z.to_array());
+        uvs.push([u, v]);
+    }
 
-    return material;
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U16(vec![0, 1, 2, 0, 2, 3]))
 }
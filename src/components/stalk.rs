@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::{
+    generator::{BakeTarget, VegetationSettings},
+    shapes::LeafShape,
+};
+
+/// Segmented stalks like bamboo, horsetails and reeds.
+#[derive(Component)]
+pub struct Stalk;
+
+/// Settings for segmented stalks like bamboo, horsetails and reeds.
+#[derive(Reflect, Component, InspectorOptions, Clone)]
+#[reflect(Component, InspectorOptions)]
+pub struct StalkSettings {
+    #[inspector(min = 0.001, max = 0.3, speed = 0.001)]
+    pub culm_w: f32,
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub taper: f32,
+
+    #[inspector(min = 1, max = 100)]
+    pub nodes: u32,
+    /// Exponent of the node distribution. Values above 1 pack the nodes closer near the base.
+    #[inspector(min = 0.1, max = 4.0, speed = 0.001)]
+    pub node_spacing: f32,
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub node_swelling: f32,
+
+    /// Length of the leaf sheath relative to the internode above it.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub sheath_len: f32,
+
+    #[inspector(min = 0, max = 64)]
+    pub leaves: u32,
+    pub leaf_shape: LeafShape,
+    /// First node carrying a leaf spray.
+    #[inspector(min = 0, max = 100)]
+    pub leaf_start: u32,
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub leaf_len: f32,
+    #[inspector(min = 0.0, max = 0.2, speed = 0.0001)]
+    pub leaf_w: f32,
+    #[inspector(min = 0.0, max = 1.5, speed = 0.001)]
+    pub leaf_angle: f32,
+
+    #[inspector(min = 1, max = 1000)]
+    pub clump_stalks: u32,
+    #[inspector(min = 0.0, max = 2.0, speed = 0.001)]
+    pub rhizome_step: f32,
+    /// Chance per stalk that the rhizome forks into a new runner.
+    #[inspector(min = 0.0, max = 1.0, speed = 0.001)]
+    pub rhizome_branching: f32,
+    pub seed: u32,
+
+    pub target: BakeTarget,
+    // To enable automatic reloading
+    pub version: u32,
+}
+
+impl StalkSettings {
+    /// A field horsetail: many short nodes with whorls of needle-like branches.
+    pub fn horsetail() -> Self {
+        StalkSettings {
+            culm_w: 0.015,
+            taper: 0.5,
+            nodes: 18,
+            node_spacing: 0.9,
+            node_swelling: 0.3,
+            sheath_len: 0.2,
+            leaves: 12,
+            leaf_shape: LeafShape::Linear,
+            leaf_start: 2,
+            leaf_len: 0.15,
+            leaf_w: 0.003,
+            leaf_angle: 0.9,
+            ..default()
+        }
+    }
+
+    /// A reed: few long internodes with long drooping leaves.
+    pub fn reed() -> Self {
+        StalkSettings {
+            culm_w: 0.01,
+            taper: 0.4,
+            nodes: 6,
+            node_spacing: 1.0,
+            node_swelling: 0.05,
+            sheath_len: 0.6,
+            leaves: 1,
+            leaf_shape: LeafShape::Linear,
+            leaf_start: 0,
+            leaf_len: 0.45,
+            leaf_w: 0.03,
+            leaf_angle: 0.3,
+            ..default()
+        }
+    }
+}
+
+impl Default for StalkSettings {
+    fn default() -> Self {
+        // bamboo
+        StalkSettings {
+            culm_w: 0.03,
+            taper: 0.3,
+
+            nodes: 10,
+            node_spacing: 1.2,
+            node_swelling: 0.15,
+
+            sheath_len: 0.3,
+
+            leaves: 5,
+            leaf_shape: LeafShape::Lanceolate,
+            leaf_start: 5,
+            leaf_len: 0.2,
+            leaf_w: 0.03,
+            leaf_angle: 0.6,
+
+            clump_stalks: 20,
+            rhizome_step: 0.3,
+            rhizome_branching: 0.2,
+            seed: 0,
+
+            target: BakeTarget::default(),
+            version: 0,
+        }
+    }
+}
+
+impl VegetationSettings for StalkSettings {
+    fn target(&self) -> &BakeTarget {
+        &self.target
+    }
+
+    fn target_mut(&mut self) -> &mut BakeTarget {
+        &mut self.target
+    }
+}
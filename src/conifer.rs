@@ -0,0 +1,294 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+use bevy_procedural_meshes::{lyon::PFill, *};
+use std::f32::consts::PI;
+
+use crate::{
+    components::{make_card_material, Conifer, ConiferSettings},
+    draw::{hash, polygon},
+    generator::VegetationGenerator,
+    impostor::{unlit_capture, ImpostorCapture},
+};
+
+/// The parts of the needle cluster that is baked to a texture.
+#[derive(Debug, Reflect, Component, PartialEq)]
+pub enum NeedlePart {
+    Twig,
+    Needles,
+}
+
+/// The parts of the 3d conifer.
+#[derive(Debug, Reflect, Component, PartialEq)]
+pub enum ConiferPart {
+    Trunk,
+    Branches,
+}
+
+/// Draws a twig with needles along the x-axis to be baked into the texture of the branch cards.
+pub fn needle_cluster_mesh(settings: &ConiferSettings, part: NeedlePart) -> PMesh<u16> {
+    let mut fill = PFill::new(0.0001);
+    fill.draw(|builder| {
+        let twig_w = 0.01;
+
+        // the main twig and its side twigs
+        let mut twigs = vec![(Vec2::ZERO, Vec2::X, 1.0)];
+        for i in 0..settings.twigs {
+            let prog = (i + 1) as f32 / (settings.twigs + 1) as f32;
+            let dir = ((i % 2) * 2) as f32 - 1.0;
+            let a = dir * (0.6 + 0.2 * hash(settings.seed, i));
+            twigs.push((
+                Vec2::new(prog, 0.0),
+                Vec2::new(a.cos(), a.sin()),
+                0.4 * (1.0 - prog),
+            ));
+        }
+
+        for (j, (start, d, len)) in twigs.into_iter().enumerate() {
+            let n = Vec2::new(-d.y, d.x);
+            let w = if j == 0 { twig_w } else { twig_w * 0.5 };
+
+            if part == NeedlePart::Twig {
+                polygon(
+                    builder,
+                    &[
+                        start + n * w,
+                        start + d * len + n * w * 0.3,
+                        start + d * len - n * w * 0.3,
+                        start - n * w,
+                    ],
+                );
+            }
+
+            if part == NeedlePart::Needles {
+                let needles = (settings.needles as f32 * len).ceil() as u32;
+                for i in 0..needles {
+                    let t = (i as f32 + 0.5) / needles as f32;
+                    let side = ((i % 2) * 2) as f32 - 1.0;
+                    let a = side * settings.needle_angle * (0.8 + 0.4 * hash(j as u32, i));
+                    let nd = d * a.cos() + n * a.sin();
+                    let nn = Vec2::new(-nd.y, nd.x);
+                    // needles get shorter towards the tip of the twig
+                    let l = settings.needle_len * (1.0 - 0.5 * t);
+                    let p = start + d * t * len;
+                    polygon(
+                        builder,
+                        &[
+                            p + nn * settings.needle_w * 0.5,
+                            p + nd * l,
+                            p - nn * settings.needle_w * 0.5,
+                        ],
+                    );
+                }
+            }
+        }
+    });
+    fill.build()
+}
+
+/// Height of the `tier`-th branch whorl.
+fn tier_height(settings: &ConiferSettings, tier: u32) -> f32 {
+    let prog = tier as f32 / settings.tiers.max(1) as f32;
+    settings.trunk_height * (settings.tier_start + (1.0 - settings.tier_start) * prog)
+}
+
+/// Length of the branches in the `tier`-th whorl. Branches get shorter towards the top.
+fn tier_branch_len(settings: &ConiferSettings, tier: u32) -> f32 {
+    let prog = tier as f32 / settings.tiers.max(1) as f32;
+    settings.branch_len * (1.0 - prog).powf(settings.branch_falloff)
+}
+
+/// Start, direction and length of every branch.
+fn branches(settings: &ConiferSettings) -> Vec<(Vec3, Vec3, f32)> {
+    let golden_angle = 2.399_963;
+    let mut res = Vec::new();
+    for tier in 0..settings.tiers {
+        let h = tier_height(settings, tier);
+        let len = tier_branch_len(settings, tier);
+        let n = settings.branches_per_tier;
+        for k in 0..n {
+            let i = tier * n + k;
+            let a = 2.0 * PI * (k as f32 + 0.3 * hash(settings.seed, i)) / n as f32
+                + golden_angle * tier as f32;
+            let dir = Vec3::new(a.cos(), -settings.branch_droop, a.sin()).normalize();
+            res.push((
+                Vec3::Y * h,
+                dir,
+                len * (0.85 + 0.3 * hash(settings.seed.wrapping_add(1), i)),
+            ));
+        }
+    }
+    res
+}
+
+/// The given part of the 3d conifer. The trunk samples the bark of the main
+/// twig in the baked needle cluster, so it can share the material of the branches.
+pub fn conifer_mesh(settings: &ConiferSettings, part: ConiferPart) -> Mesh {
+    conifer_parts(settings, &[part])
+}
+
+fn conifer_parts(settings: &ConiferSettings, parts: &[ConiferPart]) -> Mesh {
+    let frame = Conifer::frame(settings);
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    if parts.contains(&ConiferPart::Trunk) {
+        let sides = 8;
+        let rings = 8;
+        for r in 0..=rings {
+            let t = r as f32 / rings as f32;
+            let radius = settings.trunk_radius * (1.0 - (1.0 - settings.trunk_taper) * t);
+            for s in 0..=sides {
+                let a = s as f32 / sides as f32 * 2.0 * PI;
+                let n = Vec3::new(a.cos(), 0.0, a.sin());
+                positions.push((n * radius + Vec3::Y * t * settings.trunk_height).to_array());
+                normals.push(n.to_array());
+                // from the base to the tip of the main twig along its middle
+                let uv = frame.world_to_uv(Vec2::new(0.05 + 0.9 * t, 0.0));
+                uvs.push(uv.to_array());
+            }
+        }
+        for r in 0..rings {
+            for s in 0..sides {
+                let i = r * (sides + 1) + s;
+                let j = i + sides + 1;
+                indices.extend_from_slice(&[i, j, i + 1, i + 1, j, j + 1]);
+            }
+        }
+    }
+
+    if parts.contains(&ConiferPart::Branches) {
+        // every branch is a card showing the baked needle cluster
+        for (start, dir, len) in branches(settings) {
+            let side = dir.cross(Vec3::Y).normalize();
+            let normal = side.cross(dir).normalize();
+            let i = positions.len() as u32;
+            for uv in [Vec2::X, Vec2::ONE, Vec2::Y, Vec2::ZERO] {
+                // the corners of the texture in the coordinates of the needle cluster
+                let q = frame.uv_to_world(uv);
+                let p = start + (dir * q.x - side * q.y) * len;
+                positions.push(p.to_array());
+                normals.push(normal.to_array());
+                uvs.push(uv.to_array());
+            }
+            indices.extend_from_slice(&[i, i + 1, i + 2, i, i + 2, i + 3]);
+        }
+    }
+
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U32(indices))
+}
+
+/// Attachment points for cones. The cones hang from the upper side of the
+/// branches close to their tips; the local y-axis points downwards along the cone.
+pub fn cone_points(settings: &ConiferSettings) -> Vec<Transform> {
+    branches(settings)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| hash(settings.seed.wrapping_add(2), *i as u32) < settings.cone_chance)
+        .map(|(i, (start, dir, len))| {
+            let t = 0.7 + 0.25 * hash(settings.seed.wrapping_add(3), i as u32);
+            Transform::from_translation(start + dir * len * t).with_rotation(
+                Quat::from_rotation_arc(Vec3::Y, Vec3::NEG_Y.lerp(dir, 0.2).normalize()),
+            )
+        })
+        .collect()
+}
+
+/// Bakes the needle cluster and places the trunk and the branch cards.
+impl VegetationGenerator for Conifer {
+    type Settings = ConiferSettings;
+    type Part = NeedlePart;
+    type Material = StandardMaterial;
+    const NAME: &'static str = "conifer";
+    const VERSION: u32 = 1;
+
+    fn parts() -> Vec<(NeedlePart, f32)> {
+        vec![(NeedlePart::Twig, 0.0), (NeedlePart::Needles, -1.0)]
+    }
+
+    fn mesh(settings: &ConiferSettings, part: NeedlePart) -> PMesh<u16> {
+        needle_cluster_mesh(settings, part)
+    }
+
+    fn palette() -> Vec<Color> {
+        vec![Color::rgb(0.25, 0.15, 0.05), Color::rgb(0.05, 0.2, 0.08)]
+    }
+
+    fn macro_mesh(settings: &ConiferSettings) -> Mesh {
+        conifer_parts(settings, &[ConiferPart::Trunk, ConiferPart::Branches])
+    }
+
+    fn instances(settings: &ConiferSettings) -> Vec<Transform> {
+        // a small stand of upright trees growing from the ground
+        let golden_angle = 2.399_963;
+        (0..7)
+            .map(|i| {
+                let a = golden_angle * i as f32;
+                let r = 2.0 * settings.branch_len * (i as f32).sqrt();
+                Transform::from_xyz(a.cos() * r, 0.0, a.sin() * r)
+                    .with_rotation(Quat::from_rotation_y(2.0 * PI * hash(settings.seed, i)))
+                    .with_scale(Vec3::splat(
+                        0.8 + 0.4 * hash(settings.seed.wrapping_add(4), i),
+                    ))
+            })
+            .collect()
+    }
+
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material {
+        make_card_material(color, normal)
+    }
+
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>) {
+        (
+            &mut material.base_color_texture,
+            &mut material.normal_map_texture,
+        )
+    }
+
+    fn capture_material(
+        material: &Self::Material,
+        capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        unlit_capture(material, capture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macro_mesh_has_trunk_and_branches() {
+        let settings = ConiferSettings::default();
+        let trunk = conifer_mesh(&settings, ConiferPart::Trunk).count_vertices();
+        let branches = conifer_mesh(&settings, ConiferPart::Branches).count_vertices();
+        assert!(trunk > 0 && branches > 0);
+        assert_eq!(
+            Conifer::macro_mesh(&settings).count_vertices(),
+            trunk + branches
+        );
+    }
+
+    #[test]
+    fn trees_stand_on_the_ground() {
+        let settings = ConiferSettings::default();
+        let instances = Conifer::instances(&settings);
+        assert!(!instances.is_empty());
+        for transform in instances {
+            assert_eq!(transform.translation.y, 0.0);
+            assert!(transform.up().abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+}
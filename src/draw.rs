@@ -0,0 +1,45 @@
+use bevy::prelude::*;
+use bevy_procedural_meshes::lyon::{FillBuilder, PBuilder};
+
+/// Draws a closed polygon through the given points.
+pub(crate) fn polygon(builder: &mut PBuilder<FillBuilder>, points: &[Vec2]) {
+    builder.begin(points[0]);
+    for p in points.iter().skip(1) {
+        builder.line_to(*p);
+    }
+    builder.end(true);
+}
+
+/// Cheap deterministic pseudo-random number in `[0, 1)`.
+pub(crate) fn hash(seed: u32, i: u32) -> f32 {
+    ((seed as f32 * 12.9898 + i as f32 * 78.233).sin() * 43758.547)
+        .fract()
+        .abs()
+}
+
+/// Draws a polyline as a filled stroke whose width tapers linearly from `w0` to `w1`.
+pub(crate) fn stroke(builder: &mut PBuilder<FillBuilder>, points: &[Vec2], w0: f32, w1: f32) {
+    if points.len() < 2 {
+        return;
+    }
+    let last = points.len() - 1;
+    let mut left = Vec::with_capacity(points.len());
+    let mut right = Vec::with_capacity(points.len());
+    for (i, p) in points.iter().enumerate() {
+        let d = (points[i.min(last - 1) + 1] - points[i.max(1) - 1]).normalize_or_zero();
+        let n = Vec2::new(-d.y, d.x);
+        let w = 0.5 * (w0 + (w1 - w0) * i as f32 / last as f32);
+        left.push(*p + n * w);
+        right.push(*p - n * w);
+    }
+    right.reverse();
+    left.append(&mut right);
+    polygon(builder, &left);
+}
+
+/// Samples a polyline at `f` in `[0, 1]`.
+pub(crate) fn along(points: &[Vec2], f: f32) -> Vec2 {
+    let x = f.clamp(0.0, 1.0) * (points.len() - 1) as f32;
+    let i = (x.floor() as usize).min(points.len().saturating_sub(2));
+    points[i].lerp(points[(i + 1).min(points.len() - 1)], x - i as f32)
+}
@@ -0,0 +1,305 @@
+use bevy::{
+    prelude::*,
+    reflect::ReflectRef,
+    render::{color::SrgbColorSpace, render_resource::TextureFormat},
+};
+use image::{
+    error::{
+        ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError, UnsupportedErrorKind,
+    },
+    DynamicImage, ImageError, ImageFormat, RgbaImage,
+};
+use std::{
+    fmt::Write as _,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    components::BakeTask,
+    generator::{BakeFrame, VegetationGenerator},
+    ktx2::{export_ktx2, UncompressedKtx2},
+};
+
+/// File format of exported maps.
+#[derive(Debug, Clone, Copy, Default, Reflect, PartialEq)]
+pub enum ExportFormat {
+    #[default]
+    Png,
+    /// 32 bit float `OpenEXR` with linear colours.
+    Exr,
+    /// Uncompressed KTX2 including the full mip chain. Load it with Bevy's `ktx2` feature.
+    Ktx2,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Exr => "exr",
+            ExportFormat::Ktx2 => "ktx2",
+        }
+    }
+}
+
+/// The maps of the latest bake of the species `G`. Added to the settings entity.
+#[derive(Component)]
+pub struct Baked<G: VegetationGenerator> {
+    pub color: Handle<Image>,
+    pub normal: Option<Handle<Image>>,
+    /// The height map the normal map was derived from. Doubles as mask, e.g., the vein mask of leaves.
+    pub height: Option<Handle<Image>>,
+    /// Thickness of the leaves in red and ambient occlusion in green.
+    pub thickness: Option<Handle<Image>>,
+    /// Where the texture lies in the coordinates of the plant.
+    pub frame: BakeFrame,
+    _marker: PhantomData<G>,
+}
+
+impl<G: VegetationGenerator> Baked<G> {
+    pub fn new(
+        color: Handle<Image>,
+        normal: Option<Handle<Image>>,
+        height: Option<Handle<Image>>,
+        thickness: Option<Handle<Image>>,
+        frame: BakeFrame,
+    ) -> Self {
+        Baked {
+            color,
+            normal,
+            height,
+            thickness,
+            frame,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The maps by name.
+    pub fn maps(&self) -> Vec<(&'static str, &Handle<Image>)> {
+        let mut maps = vec![("color", &self.color)];
+        if let Some(normal) = &self.normal {
+            maps.push(("normal", normal));
+        }
+        if let Some(height) = &self.height {
+            maps.push(("height", height));
+        }
+        if let Some(thickness) = &self.thickness {
+            maps.push(("thickness", thickness));
+        }
+        maps
+    }
+}
+
+/// Request to write all baked maps of the species `G` to `dir`.
+#[derive(Event)]
+pub struct ExportBake<G: VegetationGenerator> {
+    pub dir: PathBuf,
+    pub format: ExportFormat,
+    _marker: PhantomData<G>,
+}
+
+impl<G: VegetationGenerator> ExportBake<G> {
+    pub fn new(dir: impl Into<PathBuf>, format: ExportFormat) -> Self {
+        ExportBake {
+            dir: dir.into(),
+            format,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Converts the pixels of the first mip level of an 8 bit RGBA or BGRA image
+/// to RGBA order. Fails for other formats and if the data is too short.
+pub fn rgba8_data(img: &Image) -> Result<Vec<u8>, ImageError> {
+    let bgra = match img.texture_descriptor.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        format => {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Unknown,
+                    UnsupportedErrorKind::GenericFeature(format!("{:?} images", format)),
+                ),
+            ))
+        }
+    };
+    let Some(data) = img.data.get(..(img.width() * img.height() * 4) as usize) else {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    };
+    let mut data = data.to_vec();
+    if bgra {
+        for p in data.chunks_exact_mut(4) {
+            p.swap(0, 2);
+        }
+    }
+    Ok(data)
+}
+
+/// Writes a single map to `path`. Colour maps are stored linear in EXR files,
+/// data maps like normals are written as they are.
+pub fn export_image(img: &Image, path: &Path, format: ExportFormat) -> Result<(), ImageError> {
+    let data = rgba8_data(img)?;
+    if format == ExportFormat::Ktx2 {
+        return export_ktx2(img, path, &UncompressedKtx2).map_err(ImageError::IoError);
+    }
+    let Some(buffer) = RgbaImage::from_raw(img.width(), img.height(), data) else {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    };
+    if format == ExportFormat::Png {
+        return buffer.save_with_format(path, ImageFormat::Png);
+    }
+    let mut buffer = DynamicImage::ImageRgba8(buffer).to_rgba32f();
+    if img.texture_descriptor.format.is_srgb() {
+        for p in buffer.pixels_mut() {
+            for c in &mut p.0[..3] {
+                *c = c.nonlinear_to_linear_srgb();
+            }
+        }
+    }
+    buffer.save_with_format(path, ImageFormat::OpenExr)
+}
+
+/// Formats the plain fields of the settings as a RON struct. Handles and meshes are skipped.
+pub(crate) fn settings_ron(settings: &dyn Reflect) -> String {
+    struct_ron(settings, 1)
+}
+
+/// Formats a struct nested `depth` levels deep in the sidecar, e.g., the [`crate::generator::BakeTarget`].
+fn struct_ron(value: &dyn Reflect, depth: usize) -> String {
+    let indent = "    ".repeat(depth);
+    let mut out = String::from("(\n");
+    if let ReflectRef::Struct(s) = value.reflect_ref() {
+        for i in 0..s.field_len() {
+            let (Some(name), Some(value)) = (s.name_at(i), s.field_at(i)) else {
+                continue;
+            };
+            let value = if let Some(v) = value.downcast_ref::<f32>() {
+                format!("{:?}", v)
+            } else if let Some(v) = value.downcast_ref::<u32>() {
+                v.to_string()
+            } else if let Some(v) = value.downcast_ref::<bool>() {
+                v.to_string()
+            } else if let ReflectRef::Enum(e) = value.reflect_ref() {
+                e.variant_name().to_string()
+            } else if let ReflectRef::Struct(_) = value.reflect_ref() {
+                struct_ron(value, depth + 1)
+            } else {
+                continue;
+            };
+            let _ = writeln!(out, "{}    {}: {},", indent, name, value);
+        }
+    }
+    let _ = write!(out, "{})", indent);
+    out
+}
+
+/// Writes all maps of a bake to `dir` as `<name>_<map>.<ext>` together with
+/// a `<name>.ron` sidecar describing the bake. Returns the written files.
+pub fn export_maps(
+    dir: &Path,
+    name: &str,
+    maps: &[(&str, &Image)],
+    settings: &dyn Reflect,
+    format: ExportFormat,
+) -> Result<Vec<PathBuf>, ImageError> {
+    std::fs::create_dir_all(dir)?;
+    let mut files = Vec::with_capacity(maps.len() + 1);
+    let mut map_list = String::new();
+    let (mut width, mut height) = (0, 0);
+    for (map, img) in maps {
+        let file = format!("{}_{}.{}", name, map, format.extension());
+        export_image(img, &dir.join(&file), format)?;
+        let _ = writeln!(map_list, "        \"{}\": \"{}\",", map, file);
+        (width, height) = (img.width(), img.height());
+        files.push(dir.join(file));
+    }
+
+    let metadata = format!(
+        "(\n    generator: \"{}\",\n    format: \"{}\",\n    width: {},\n    height: {},\n    maps: {{\n{}    }},\n    settings: {},\n)\n",
+        name,
+        format.extension(),
+        width,
+        height,
+        map_list,
+        settings_ron(settings)
+    );
+    let path = dir.join(format!("{}.ron", name));
+    std::fs::write(&path, metadata)?;
+    files.push(path);
+
+    Ok(files)
+}
+
+/// Handles [`ExportBake`] events. Every bake task is exported under its own name.
+pub fn export_bakes<G: VegetationGenerator>(
+    mut events: EventReader<ExportBake<G>>,
+    images: Res<Assets<Image>>,
+    query: Query<(&G::Settings, &Baked<G>, &BakeTask)>,
+) {
+    for event in events.read() {
+        if query.is_empty() {
+            warn!("Nothing to export, {} has not been baked yet", G::NAME);
+            continue;
+        }
+        for (settings, baked, task) in query.iter() {
+            let maps: Vec<(&str, &Image)> = baked
+                .maps()
+                .into_iter()
+                .filter_map(|(name, handle)| images.get(handle).map(|img| (name, img)))
+                .collect();
+            match export_maps(
+                &event.dir,
+                &task.name,
+                &maps,
+                settings.as_reflect(),
+                event.format,
+            ) {
+                Ok(files) => info!("Exported {} files to {:?}", files.len(), event.dir),
+                Err(err) => error!("Failed to export {}: {}", task.name, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_images::filled;
+
+    fn exported_exr(img: &Image, name: &str) -> [f32; 4] {
+        let path = std::env::temp_dir().join(format!("bevy_procedural_vegetation_{}.exr", name));
+        export_image(img, &path, ExportFormat::Exr).unwrap();
+        let exr = image::open(&path).unwrap().to_rgba32f();
+        std::fs::remove_file(&path).unwrap();
+        exr.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn exr_linearizes_colour_maps_only() {
+        let color = exported_exr(
+            &filled(UVec2::ONE, TextureFormat::Rgba8UnormSrgb, [128; 4]),
+            "color",
+        );
+        assert!((color[0] - 0.2158).abs() < 1e-3);
+        assert!((color[3] - 128.0 / 255.0).abs() < 1e-3);
+
+        let normal = exported_exr(
+            &filled(UVec2::ONE, TextureFormat::Rgba8Unorm, [128; 4]),
+            "normal",
+        );
+        assert!((normal[0] - 128.0 / 255.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        let img = filled(UVec2::ONE, TextureFormat::R32Float, [0; 4]);
+        assert!(rgba8_data(&img).is_err());
+        let mut img = filled(UVec2::ONE, TextureFormat::Rgba8Unorm, [0; 4]);
+        img.data.clear();
+        assert!(rgba8_data(&img).is_err());
+    }
+}
@@ -1,19 +1,32 @@
-use bevy::prelude::*;
+use bevy::{pbr::ExtendedMaterial, prelude::*};
 use bevy_procedural_meshes::{
-    lyon::{FillBuilder, PBuilder, PFill},
+    lyon::{FillBuilder, PBuilder},
     *,
 };
 
-use crate::components::FernSettings;
+use crate::{
+    bake::{ambient_occlusion, blur, height_field, mask_image, normal_from_height},
+    components::{make_fern_material, make_fern_mesh, Fern, FernMaterial, FernSettings},
+    draw::hash,
+    generator::VegetationGenerator,
+    impostor::ImpostorCapture,
+    palette::{Palette, PaletteCoords},
+    season::{season_state, SeasonState},
+};
 
 #[derive(Debug, Reflect, Component, PartialEq)]
 pub enum FernPart {
     Stem,
     LeafletTop,
     LeafletBottom,
+    Midrib,
 }
 
-pub fn fern_mesh(settings: &FernSettings, part: FernPart) -> PMesh<u16> {
+/// The mesh of a part in the coordinates of the frond: the rachis starts at
+/// `(0.1, 0)` and runs along x, the leaflets grow along y. Every leaflet is
+/// filled on its own, so the leaflet of every vertex is known, see
+/// [`VegetationGenerator::mesh_with_leaflets`].
+pub fn fern_mesh_with_leaflets(settings: &FernSettings, part: FernPart) -> (PMesh<u16>, Vec<u32>) {
     /*let mut fill = PFill::new(0.01);
     fill.draw(|builder| {
         builder.add_circle(Vec2::ZERO, 1.0, Winding::Positive);
@@ -22,120 +35,332 @@ pub fn fern_mesh(settings: &FernSettings, part: FernPart) -> PMesh<u16> {
     mesh.flip_yz();
     return mesh;*/
 
-    let mut fill = PFill::new(0.0001);
-    fill.draw(|builder| {
-        // just a circle gives also a nice mesh!
-        //builder.add_circle(Vec2::ZERO, 1.0, Winding::Positive);
+    let tol = 0.0001;
+    let mut mesh = PMesh::new();
+    // just a circle gives also a nice mesh!
+    //mesh.fill(tol, |builder| builder.add_circle(Vec2::ZERO, 1.0, Winding::Positive));
 
-        // Or a triangle
-        /*
-        builder.begin(Vec2::new(-1.0, 1.0));
-        builder.line_to(Vec2::new(1.0, -1.0));
-        builder.line_to(Vec2::new(1.0, 1.0));
-        builder.end(true);
-        */
+    // Or a triangle
+    /*
+    builder.begin(Vec2::new(-1.0, 1.0));
+    builder.line_to(Vec2::new(1.0, -1.0));
+    builder.line_to(Vec2::new(1.0, 1.0));
+    builder.end(true);
+    */
 
-        let stem_w = settings.stem_w;
-        let stem_w2 = settings.stem_w2;
+    let stem_w = settings.stem_w;
+    let stem_w2 = settings.stem_w2;
 
-        if part == FernPart::Stem {
+    if part == FernPart::Stem {
+        mesh.fill(tol, |builder| {
             builder.begin(Vec2::new(0.0, stem_w));
             builder.line_to(Vec2::new(1.0, stem_w2));
             builder.line_to(Vec2::new(1.0, -stem_w2));
             builder.line_to(Vec2::new(0.0, -stem_w));
             builder.end(true);
-        }
+        });
+    }
+    // the rachis belongs to the first leaflet
+    let mut vertex_leaflets = vec![0; mesh.get_vertices().len()];
 
-        fn leaflet(
-            start: Vec2,
-            leaflets: u32,
-            leaflet_len: f32,
-            curve: f32,
-            l0: f32,
-            dir: f32,
-            builder: &mut PBuilder<FillBuilder>,
-            settings: &FernSettings,
-            part: &FernPart,
-        ) {
-            builder.push();
-            let a0 = leaflet_len / ((leaflets + 1) as f32 * 0.5);
-            builder.translate(start);
-            for i in 0..(leaflets - 2) {
-                let prog = 1.0 - i as f32 / leaflets as f32;
-                let l = l0 * prog * leaflet_len;
-                let a = dir * a0 * prog;
-                let step = Vec2::new(0.0, a);
-                builder.rotate(-curve * 2.0 * dir); // TODO: rotation can be better controlled. However, I like the current ones since they have more imperfections
-                let slant = settings.slant;
-                let thinning = settings.thinning;
-                let stomp = settings.stomp;
-
-                if *part == FernPart::LeafletTop {
-                    builder
-                        .begin_here()
-                        .quadratic_bezier_to(
-                            Vec2::new(l * stomp, thinning * a * (-0.5 + slant)),
-                            Vec2::new(l, thinning * a * (0.5 + slant)),
-                        )
-                        .quadratic_bezier_to(Vec2::new(l, thinning * a * (1.0 + slant)), step)
-                        .close();
-                }
+    #[allow(clippy::too_many_arguments)]
+    fn leaflet(
+        start: Vec2,
+        leaflets: u32,
+        leaflet_len: f32,
+        curve: f32,
+        l0: f32,
+        dir: f32,
+        builder: &mut PBuilder<FillBuilder>,
+        settings: &FernSettings,
+        part: &FernPart,
+        index: u32,
+        state: &SeasonState,
+    ) {
+        builder.push();
+        let a0 = leaflet_len / ((leaflets + 1) as f32 * 0.5);
+        builder.translate(start);
+        for i in 0..(leaflets - 2) {
+            let prog = 1.0 - i as f32 / leaflets as f32;
+            // curled leaflets look narrower from above
+            let l = l0 * prog * leaflet_len * (1.0 - 0.3 * state.curl);
+            let lost = hash(index, i) < state.loss;
+            let a = dir * a0 * prog;
+            let step = Vec2::new(0.0, a);
+            builder.rotate(-curve * 2.0 * dir); // TODO: rotation can be better controlled. However, I like the current ones since they have more imperfections
+            let slant = settings.slant;
+            let thinning = settings.thinning;
+            let stomp = settings.stomp;
 
-                if *part == FernPart::LeafletBottom {
-                    let l2 = -l;
-                    builder
-                        .begin_here()
-                        .quadratic_bezier_to(
-                            Vec2::new(l2 * stomp, thinning * a * (-0.5 + slant)),
-                            Vec2::new(l2, thinning * a * (0.5 + slant)),
-                        )
-                        .quadratic_bezier_to(Vec2::new(l2, thinning * a * (1.0 + slant)), step)
-                        .close();
-                }
+            if *part == FernPart::LeafletTop && !lost {
+                builder
+                    .begin_here()
+                    .quadratic_bezier_to(
+                        Vec2::new(l * stomp, thinning * a * (-0.5 + slant)),
+                        Vec2::new(l, thinning * a * (0.5 + slant)),
+                    )
+                    .quadratic_bezier_to(Vec2::new(l, thinning * a * (1.0 + slant)), step)
+                    .close();
+            }
 
-                if *part == FernPart::Stem {
-                    let stemlet_width = Vec2::new(0.0015, 0.0);
+            if *part == FernPart::LeafletBottom && !lost {
+                let l2 = -l;
+                builder
+                    .begin_here()
+                    .quadratic_bezier_to(
+                        Vec2::new(l2 * stomp, thinning * a * (-0.5 + slant)),
+                        Vec2::new(l2, thinning * a * (0.5 + slant)),
+                    )
+                    .quadratic_bezier_to(Vec2::new(l2, thinning * a * (1.0 + slant)), step)
+                    .close();
+            }
+
+            if *part == FernPart::Midrib && !lost {
+                let midrib_width = Vec2::new(0.0, 0.001);
+                for l2 in [l, -l] {
                     builder
-                        .begin(stemlet_width)
-                        .line_to(step + stemlet_width)
-                        .line_to(step - stemlet_width)
-                        .line_to(-stemlet_width)
+                        .begin(midrib_width)
+                        .line_to(Vec2::new(l2 * 0.9, thinning * a * (0.5 + slant)))
+                        .line_to(-midrib_width)
                         .close();
                 }
+            }
 
-                builder.translate(step);
-                // p += step;
+            if *part == FernPart::Stem {
+                let stemlet_width = Vec2::new(0.0015, 0.0);
+                builder
+                    .begin(stemlet_width)
+                    .line_to(step + stemlet_width)
+                    .line_to(step - stemlet_width)
+                    .line_to(-stemlet_width)
+                    .close();
             }
-            builder.pop();
+
+            builder.translate(step);
+            // p += step;
         }
+        builder.pop();
+    }
 
-        let leaflets = settings.leaflets1;
-        let mut px = 0.1;
-        for i in 0..leaflets {
-            let prog = i as f32 / leaflets as f32;
-            let l0 = settings.l0;
-            let leaflet_len = 1.0 - prog.powf(settings.leafshape_exp);
-            let dir = ((i % 2) * 2) as f32 - 1.0;
+    let state = season_state(settings.season, settings.age);
+    let leaflets = settings.leaflets1;
+    let mut px = 0.1;
+    for i in 0..leaflets {
+        let prog = i as f32 / leaflets as f32;
+        let l0 = settings.l0;
+        let leaflet_len = 1.0 - prog.powf(settings.leafshape_exp);
+        let dir = ((i % 2) * 2) as f32 - 1.0;
+        mesh.fill(tol, |builder| {
             leaflet(
                 Vec2::new(px, dir * (stem_w * (1.0 - prog) + stem_w2 * prog)),
                 settings.leaflets2,
                 leaflet_len,
-                settings.curvature * (1.0 - 0.5 * prog),
+                settings.curvature * (1.0 - 0.5 * prog) * (1.0 + state.droop),
                 l0,
                 dir,
                 builder,
                 settings,
                 &part,
+                i,
+                &state,
             );
-            px += l0 * leaflet_len * settings.leaflet_spacing * 0.5;
+        });
+        vertex_leaflets.resize(mesh.get_vertices().len(), i);
+        px += l0 * leaflet_len * settings.leaflet_spacing * 0.5;
+    }
+    (mesh, vertex_leaflets)
+}
+
+pub fn fern_mesh(settings: &FernSettings, part: FernPart) -> PMesh<u16> {
+    fern_mesh_with_leaflets(settings, part).0
+}
+
+impl VegetationGenerator for Fern {
+    type Settings = FernSettings;
+    type Part = FernPart;
+    type Material = ExtendedMaterial<StandardMaterial, FernMaterial>;
+    const NAME: &'static str = "fern";
+    const VERSION: u32 = 3;
+
+    fn parts() -> Vec<(FernPart, f32)> {
+        vec![
+            (FernPart::Stem, 0.0),
+            (FernPart::LeafletTop, -0.8),
+            (FernPart::LeafletBottom, -1.0),
+            (FernPart::Midrib, -0.5),
+        ]
+    }
+
+    fn mesh(settings: &FernSettings, part: FernPart) -> PMesh<u16> {
+        fern_mesh(settings, part)
+    }
+
+    fn mesh_with_leaflets(settings: &FernSettings, part: FernPart) -> (PMesh<u16>, Vec<u32>) {
+        fern_mesh_with_leaflets(settings, part)
+    }
+
+    fn palette() -> Vec<Color> {
+        vec![
+            Color::rgb(0.1, 0.2, 0.0),
+            Color::rgb(0.05, 0.3, 0.0),
+            Color::rgb(0.05, 0.36, 0.05),
+            Color::rgb(0.08, 0.38, 0.05),
+        ]
+    }
+
+    fn palettes() -> Vec<Palette> {
+        let colors = Self::palette();
+        let leaflet = |color: Color, seed: u32| Palette {
+            base: color,
+            tip: Color::rgb(0.12, 0.42, 0.04),
+            leaflet_tip: Color::rgb(0.16, 0.45, 0.06),
+            leaflet_gradient: 0.6,
+            hue_jitter: 6.0,
+            value_jitter: 0.04,
+            browning: 0.08,
+            seed,
+            ..Palette::flat(color)
+        };
+        vec![
+            Palette {
+                tip: Color::rgb(0.12, 0.26, 0.02),
+                ..Palette::flat(colors[0])
+            },
+            leaflet(colors[1], 1),
+            leaflet(colors[2], 2),
+            Palette::flat(colors[3]),
+        ]
+    }
+
+    fn palette_coords(settings: &FernSettings, position: Vec2, leaflet: u32) -> PaletteCoords {
+        let prog = leaflet as f32 / settings.leaflets1.max(1) as f32;
+        let leaflet_len = 1.0 - prog.powf(settings.leafshape_exp);
+        PaletteCoords {
+            along_rachis: position.x.clamp(0.0, 1.0),
+            along_leaflet: (position.y.abs() / leaflet_len.max(0.001)).clamp(0.0, 1.0),
+            leaflet,
         }
-    });
-    let mut fern = fill.build();
-    fern.translate(-0.5, 0.0, 0.0)
-        .scale(settings.width as f32, settings.height as f32 / 2.0, 1.0);
+    }
+
+    fn seasonal_color(settings: &FernSettings, coords: PaletteCoords, color: Color) -> Color {
+        let state = season_state(settings.season, settings.age);
+        // the tips and some leaflets change colour first
+        let early = hash(settings.leaflets1, coords.leaflet) * 0.3;
+        let t = (state.tint * (0.6 + 0.4 * coords.along_leaflet + early)).clamp(0.0, 1.0);
+        let a = Vec4::from(color.as_linear_rgba_f32());
+        let b = Vec4::from(state.color.as_linear_rgba_f32());
+        let c = a.lerp(b.truncate().extend(a.w), t);
+        Color::rgba_linear(c.x, c.y, c.z, c.w)
+    }
+
+    fn dilation(settings: &FernSettings) -> u32 {
+        settings.dilation
+    }
+
+    fn sdf_spread(settings: &FernSettings) -> Option<f32> {
+        settings.sdf.then_some(settings.sdf_spread)
+    }
+
+    fn height_palette() -> Vec<Color> {
+        // the parts in front are higher, so the stem is the highest ridge and
+        // the leaflets fold along their midribs; they are domed by the blur
+        Self::parts()
+            .into_iter()
+            .map(|(_, depth)| {
+                let h = 1.0 + 0.5 * depth;
+                Color::rgb(h, h, h)
+            })
+            .collect()
+    }
+
+    fn normal_map(settings: &FernSettings, height: &Image) -> Option<Image> {
+        let radius = (height.width() / 512).max(1) as usize * 2;
+        let field = blur(&height_field(height), height.width() as usize, radius);
+        Some(normal_from_height(
+            &field,
+            height.width(),
+            height.height(),
+            settings.normal_strength,
+        ))
+    }
+
+    fn thickness_map(settings: &FernSettings, height: &Image) -> Option<Image> {
+        // the stem and midribs are the thickest parts, so the height doubles as thickness
+        let width = height.width() as usize;
+        let field = height_field(height);
+        let thickness = blur(&field, width, (width / 512).max(1));
+        // the stem shadows the leaflets and the midribs the blades next to them
+        let ao = ambient_occlusion(&field, width, (width / 64).max(1), settings.ao_strength);
+        Some(mask_image(&thickness, &ao, height.width(), height.height()))
+    }
+
+    fn macro_mesh(_settings: &FernSettings) -> Mesh {
+        make_fern_mesh()
+    }
+
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material {
+        make_fern_material(color, normal)
+    }
+
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>) {
+        (
+            &mut material.base.base_color_texture,
+            &mut material.base.normal_map_texture,
+        )
+    }
+
+    fn capture_material(
+        material: &Self::Material,
+        capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        let mut material = material.clone();
+        material.extension.capture = match capture {
+            ImpostorCapture::Color => 3,
+            ImpostorCapture::Normal => 1,
+            ImpostorCapture::Depth => 2,
+        };
+        Some(material)
+    }
+
+    fn apply_settings(
+        settings: &FernSettings,
+        material: &mut Self::Material,
+        thickness: Option<Handle<Image>>,
+    ) {
+        let state = season_state(settings.season, settings.age);
+        material.extension.translucency = settings.translucency;
+        material.extension.droop = state.droop;
+        material.extension.curl = state.curl;
+        material.extension.sdf = settings.sdf as u32;
+        material.extension.thickness = thickness;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaflets(settings: &FernSettings) -> Vec<u32> {
+        let (mesh, mut leaflets) = fern_mesh_with_leaflets(settings, FernPart::LeafletTop);
+        assert_eq!(leaflets.len(), mesh.get_vertices().len());
+        leaflets.dedup();
+        leaflets
+    }
 
-    //fern.flip_yz();
-    fern.scale(-1.0, 1.0, 1.0);
+    #[test]
+    fn vertices_keep_their_leaflet() {
+        let settings = FernSettings::default();
+        let straight = leaflets(&settings);
+        assert!(straight.windows(2).all(|w| w[0] < w[1]));
+        assert!(straight.len() > 1);
 
-    return fern;
+        // curled leaflets reach over their neighbours but keep their index
+        let curled = FernSettings {
+            curvature: settings.curvature * 4.0,
+            ..settings.clone()
+        };
+        assert_eq!(leaflets(&curled), straight);
+    }
 }
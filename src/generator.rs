@@ -0,0 +1,455 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::VertexAttributeValues, render_asset::RenderAssetUsages},
+};
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+use bevy_procedural_meshes::*;
+
+use crate::{
+    bake::{dilate, signed_distance_field, with_alpha},
+    impostor::ImpostorCapture,
+    palette::{BakePalette, Palette, PaletteCoords},
+};
+
+/// The largest supported [`BakeTarget::supersampling`].
+pub const MAX_SUPERSAMPLING: u32 = 4;
+
+/// Where the parts of a plant are baked to. Embedded in the settings of every species.
+#[derive(Reflect, InspectorOptions, Debug, Clone)]
+#[reflect(InspectorOptions)]
+pub struct BakeTarget {
+    #[inspector(min = 8, max = 4096)]
+    pub width: u32,
+    #[inspector(min = 8, max = 4096)]
+    pub height: u32,
+    /// Renders the bake at this multiple of the resolution to smooth thin
+    /// stemlets. Changing it restarts the bake at the new size. The bake
+    /// cameras use the global `Msaa` resource like every other camera, so
+    /// multisampling can't be chosen per bake and supersampling is used instead.
+    #[inspector(min = 1, max = 4)]
+    pub supersampling: u32,
+    /// Free texels around the plant when fitting it to the texture, see [`BakeFrame::fit`].
+    #[inspector(min = 0.0, max = 256.0, speed = 0.1)]
+    pub padding: f32,
+
+    /// The meshes of the individual parts in the order of [`VegetationGenerator::parts`].
+    pub meshes: Vec<AssetId<Mesh>>,
+    pub render_target: Option<Handle<Image>>,
+}
+
+impl BakeTarget {
+    pub fn new(width: u32, height: u32) -> Self {
+        BakeTarget {
+            width,
+            height,
+            supersampling: 1,
+            padding: 4.0,
+            meshes: vec![],
+            render_target: None,
+        }
+    }
+}
+
+impl Default for BakeTarget {
+    fn default() -> Self {
+        BakeTarget::new(512, 512)
+    }
+}
+
+/// Settings component of a [`VegetationGenerator`]. Gives the plugin access to
+/// the bake target without knowing the species.
+pub trait VegetationSettings: Component + Default + Reflect + Clone {
+    /// The bake target embedded in the settings.
+    fn target(&self) -> &BakeTarget;
+
+    fn target_mut(&mut self) -> &mut BakeTarget;
+
+    /// Resolution of the baked texture.
+    fn size(&self) -> UVec2 {
+        UVec2::new(self.target().width, self.target().height)
+    }
+
+    /// Sets the resolution of the baked texture.
+    fn set_size(&mut self, size: UVec2) {
+        let target = self.target_mut();
+        target.width = size.x;
+        target.height = size.y;
+    }
+
+    /// The bake is rendered at this multiple of the resolution and filtered
+    /// down to it, see [`BakeTarget::supersampling`]. At most [`MAX_SUPERSAMPLING`].
+    fn supersampling(&self) -> u32 {
+        self.target().supersampling.clamp(1, MAX_SUPERSAMPLING)
+    }
+
+    /// The meshes of the individual parts in the order of [`VegetationGenerator::parts`].
+    fn meshes(&self) -> &[AssetId<Mesh>] {
+        &self.target().meshes
+    }
+
+    /// Connects the settings to the meshes of the parts and the image they are baked into.
+    fn set_target(&mut self, meshes: Vec<AssetId<Mesh>>, render_target: Handle<Image>) {
+        let target = self.target_mut();
+        target.meshes = meshes;
+        target.render_target = Some(render_target);
+    }
+}
+
+/// Maps the texture coordinates of a bake to the coordinates the generator
+/// builds the plant in. An extent may be negative if the texture is mirrored.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct BakeFrame {
+    /// The point of the plant at uv `(0, 0)`, i.e., the top left of the texture.
+    pub origin: Vec2,
+    /// The offset from `origin` to the point at uv `(1, 1)`.
+    pub extent: Vec2,
+}
+
+impl BakeFrame {
+    /// Covers `[0, 1]×[-1, 1]` with the base of the plant, at the origin, on
+    /// the right. The generators build their parts along the x axis in these units.
+    pub const UNIT: BakeFrame = BakeFrame {
+        origin: Vec2::new(1.0, 1.0),
+        extent: Vec2::new(-1.0, -2.0),
+    };
+
+    pub fn uv_to_world(&self, uv: Vec2) -> Vec2 {
+        self.origin + uv * self.extent
+    }
+
+    pub fn world_to_uv(&self, position: Vec2) -> Vec2 {
+        (position - self.origin) / self.extent
+    }
+
+    /// From the pixels of the 2d meshes, centered on the texture, to the plant.
+    pub fn pixel_to_world(&self, size: UVec2, pixel: Vec2) -> Vec2 {
+        let size = size.as_vec2();
+        self.uv_to_world(Vec2::new(pixel.x / size.x + 0.5, 0.5 - pixel.y / size.y))
+    }
+
+    /// Moves a mesh built in the coordinates of the plant to the pixels of a texture of `size`.
+    pub fn transform_mesh(&self, mesh: &mut PMesh<u16>, size: UVec2) {
+        let size = size.as_vec2();
+        mesh.translate(-self.origin.x, -self.origin.y, 0.0)
+            .scale(size.x / self.extent.x, -size.y / self.extent.y, 1.0)
+            .translate(-size.x / 2.0, size.y / 2.0, 0.0);
+    }
+
+    /// Centers `bounds` in a frame with the orientation and aspect ratio of
+    /// this one, leaving `padding` texels on every side of a texture of `size`.
+    pub fn fit(&self, bounds: Rect, size: UVec2, padding: f32) -> Self {
+        let fill = (Vec2::ONE - 2.0 * padding / size.as_vec2()).max(Vec2::splat(0.01));
+        let aspect = self.extent.abs() / self.extent.x.abs();
+        let scale = (bounds.size() / fill / aspect).max_element();
+        let extent = self.extent.signum() * aspect * scale;
+        BakeFrame {
+            origin: bounds.center() - extent / 2.0,
+            extent,
+        }
+    }
+}
+
+/// The bounding box of the vertices of a 2d mesh.
+pub fn mesh_bounds(mesh: PMesh<u16>) -> Option<Rect> {
+    let mesh = mesh.to_bevy(RenderAssetUsages::all());
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+    positions
+        .iter()
+        .map(|p| Rect::from_center_size(Vec2::new(p[0], p[1]), Vec2::ZERO))
+        .reduce(|a, b| a.union(b))
+}
+
+/// A species of plant. Implementing this trait is all that is needed to
+/// generate, bake and place a new plant type using the [`crate::components::VegetationPlugin`].
+pub trait VegetationGenerator: Send + Sync + 'static {
+    /// The settings driving the generator.
+    type Settings: VegetationSettings;
+
+    /// The parts of the plant. Every part is baked with its own colour.
+    type Part: Send + Sync + 'static;
+
+    /// The material of the macro mesh.
+    type Material: Material;
+
+    /// Unique name of the species.
+    const NAME: &'static str;
+
+    /// Bump whenever the output of the generator changes to invalidate cached bakes.
+    const VERSION: u32 = 0;
+
+    /// The parts in drawing order together with their depth relative to the first one.
+    fn parts() -> Vec<(Self::Part, f32)>;
+
+    /// Generates the 2d mesh of a single part in the coordinates of the plant,
+    /// see [`VegetationGenerator::frame`].
+    fn mesh(settings: &Self::Settings, part: Self::Part) -> PMesh<u16>;
+
+    /// Like [`VegetationGenerator::mesh`] but also returns the leaflet of every
+    /// vertex, see [`PaletteCoords::leaflet`]. Defaults to leaflet 0 everywhere.
+    fn mesh_with_leaflets(settings: &Self::Settings, part: Self::Part) -> (PMesh<u16>, Vec<u32>) {
+        let mesh = Self::mesh(settings, part);
+        let leaflets = vec![0; mesh.get_vertices().len()];
+        (mesh, leaflets)
+    }
+
+    /// The default colours of the parts when baking the texture.
+    fn palette() -> Vec<Color>;
+
+    /// The palettes of the parts when baking the texture. Defaults to the flat
+    /// colours of [`VegetationGenerator::palette`].
+    fn palettes() -> Vec<Palette> {
+        Self::palette().into_iter().map(Palette::flat).collect()
+    }
+
+    /// Where a point of the `leaflet`, given in the coordinates of [`VegetationGenerator::frame`],
+    /// lies on the plant. Drives the gradients of the [`Palette`]. By default, the rachis runs along the x axis and
+    /// the leaflets away from it.
+    fn palette_coords(_settings: &Self::Settings, position: Vec2, leaflet: u32) -> PaletteCoords {
+        PaletteCoords {
+            along_rachis: position.x.clamp(0.0, 1.0),
+            along_leaflet: position.y.abs().clamp(0.0, 1.0),
+            leaflet,
+        }
+    }
+
+    /// Where the texture lies in the coordinates of the plant. Defaults to
+    /// [`BakeFrame::UNIT`] fitted to the bounds of all parts.
+    fn frame(settings: &Self::Settings) -> BakeFrame {
+        let bounds = Self::parts()
+            .into_iter()
+            .filter_map(|(part, _)| mesh_bounds(Self::mesh(settings, part)))
+            .reduce(|a, b| a.union(b));
+        let Some(bounds) = bounds else {
+            return BakeFrame::UNIT;
+        };
+        BakeFrame::UNIT.fit(bounds, settings.size(), settings.target().padding)
+    }
+
+    /// The heights of the parts when baking the normal map. The normal map is
+    /// only baked if this is not empty. The height map is rasterized on the CPU,
+    /// see [`crate::raster::bake_from_color`].
+    fn height_palette() -> Vec<Color> {
+        vec![]
+    }
+
+    /// Derives the tangent-space normal map from the baked height map.
+    fn normal_map(_settings: &Self::Settings, _height: &Image) -> Option<Image> {
+        None
+    }
+
+    /// How many texels the colours are bled into the transparent surroundings
+    /// of the colour map, see [`crate::bake::dilate`].
+    fn dilation(_settings: &Self::Settings) -> u32 {
+        0
+    }
+
+    /// The spread in texels of the signed distance field replacing the alpha of
+    /// the colour map, or `None` to keep the plain coverage.
+    fn sdf_spread(_settings: &Self::Settings) -> Option<f32> {
+        None
+    }
+
+    /// Derives the mask map from the baked height map. The red channel holds the
+    /// thickness used for the transmission of back-lit leaves, the green channel
+    /// the baked ambient occlusion.
+    fn thickness_map(_settings: &Self::Settings, _height: &Image) -> Option<Image> {
+        None
+    }
+
+    /// The 3d mesh showing the baked texture.
+    fn macro_mesh(settings: &Self::Settings) -> Mesh;
+
+    /// The material of the macro mesh given the baked textures.
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material;
+
+    /// The colour and normal texture slots of the material.
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>);
+
+    /// Adjusts the colour of the palette to the state of the plant, e.g., its season.
+    fn seasonal_color(_settings: &Self::Settings, _coords: PaletteCoords, color: Color) -> Color {
+        color
+    }
+
+    /// Passes the baked mask map and the settings driving the shader to the material.
+    fn apply_settings(
+        _settings: &Self::Settings,
+        _material: &mut Self::Material,
+        _thickness: Option<Handle<Image>>,
+    ) {
+    }
+
+    /// The material rendering the macro mesh for an impostor capture. The colour
+    /// is captured unlit since the impostor is lit when it is drawn, see
+    /// [`crate::impostor::unlit_capture`]. Returns `None` for unsupported
+    /// captures, by default all of them.
+    fn capture_material(
+        _material: &Self::Material,
+        _capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        None
+    }
+
+    /// Where to place instances of the macro mesh.
+    fn instances(_settings: &Self::Settings) -> Vec<Transform> {
+        (0..30)
+            .map(|i| {
+                let s = (i as f32 * 100.0).sin() + 2.0;
+                Transform::from_xyz(
+                    ((1012.0 * i as f32).sin() * 100000.0) % 10.0,
+                    s / 2.0,
+                    ((432.0 * i as f32).sin() * 100000.0) % 10.0,
+                )
+                .with_scale(Vec3::splat(s))
+            })
+            .collect()
+    }
+}
+
+/// Derives all maps of a bake from the rendered colour and height map. The
+/// maps are named like the ones of [`crate::export::Baked`].
+pub fn derive_maps<G: VegetationGenerator>(
+    settings: &G::Settings,
+    color: Image,
+    height: Option<Image>,
+) -> Vec<(&'static str, Image)> {
+    let color = match G::dilation(settings) {
+        0 => color,
+        texels => dilate(&color, texels),
+    };
+    let color = match G::sdf_spread(settings) {
+        Some(spread) => with_alpha(&color, &signed_distance_field(&color, spread)),
+        None => color,
+    };
+    let mut maps = vec![("color", color)];
+    if let Some(height) = height {
+        if let Some(normal) = G::normal_map(settings, &height) {
+            maps.push(("normal", normal));
+        }
+        if let Some(thickness) = G::thickness_map(settings, &height) {
+            maps.push(("thickness", thickness));
+        }
+        maps.push(("height", height));
+    }
+    maps
+}
+
+/// The leaflet of every triangle of `mesh` given the leaflets of its vertices.
+/// Converting the mesh with `bevy_set` keeps the order of the triangles.
+pub(crate) fn triangle_leaflets(mesh: &PMesh<u16>, leaflets: &[u32]) -> Vec<u32> {
+    mesh.iter_faces()
+        .map(|face| leaflets.get(face[0]).copied().unwrap_or(0))
+        .collect()
+}
+
+/// Regenerates the meshes of all parts whenever the settings or the palette change.
+pub fn update_meshes<G: VegetationGenerator>(
+    query: Query<
+        (&G::Settings, Option<&BakePalette>),
+        Or<(Changed<G::Settings>, Changed<BakePalette>)>,
+    >,
+    mut assets: ResMut<Assets<Mesh>>,
+) {
+    for (settings, palette) in query.iter() {
+        let frame = G::frame(settings);
+        // the parts are rendered at the supersampled size
+        let scale = settings.supersampling() as f32;
+        for (i, ((part, _), id)) in G::parts().into_iter().zip(settings.meshes()).enumerate() {
+            // skip parts whose mesh was removed, e.g., with a despawned bake
+            let Some(target) = assets.get_mut(*id) else {
+                continue;
+            };
+            let (mut mesh, leaflets) = G::mesh_with_leaflets(settings, part);
+            frame.transform_mesh(&mut mesh, settings.size());
+            if scale != 1.0 {
+                mesh.scale(scale, scale, 1.0);
+            }
+            mesh.bevy_set(target);
+            if let Some(palette) = palette.and_then(|p| p.0.get(i)) {
+                let triangles = triangle_leaflets(&mesh, &leaflets);
+                paint::<G>(settings, &frame, target, palette, &triangles, scale);
+            }
+        }
+    }
+}
+
+/// Evaluates the palette at every vertex and stores it as vertex colour. The
+/// mesh is `scale` times larger than the texture of the settings, placed by
+/// `frame`, and its triangles belong to the given leaflets.
+fn paint<G: VegetationGenerator>(
+    settings: &G::Settings,
+    frame: &BakeFrame,
+    mesh: &mut Mesh,
+    palette: &Palette,
+    triangles: &[u32],
+    scale: f32,
+) {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return;
+    };
+    // `bevy_set` duplicates the vertices of meshes without normals, so those
+    // list the vertices of every triangle in order
+    let mut leaflets = vec![0; positions.len()];
+    match mesh.indices() {
+        Some(indices) => {
+            for (k, vertex) in indices.iter().enumerate() {
+                leaflets[vertex] = triangles.get(k / 3).copied().unwrap_or(0);
+            }
+        }
+        None => {
+            for (k, leaflet) in leaflets.iter_mut().enumerate() {
+                *leaflet = triangles.get(k / 3).copied().unwrap_or(0);
+            }
+        }
+    }
+    let colors: Vec<[f32; 4]> = positions
+        .iter()
+        .zip(leaflets)
+        .map(|(p, leaflet)| {
+            let pixel = Vec2::new(p[0], p[1]) / scale;
+            let position = frame.pixel_to_world(settings.size(), pixel);
+            let coords = G::palette_coords(settings, position, leaflet);
+            G::seasonal_color(settings, coords, palette.eval(coords)).as_linear_rgba_f32()
+        })
+        .collect();
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Conifer, Kelp, Leaf, Stalk};
+
+    fn assert_framed<G: VegetationGenerator>() {
+        let settings = G::Settings::default();
+        let frame = G::frame(&settings);
+        let half = settings.size().as_vec2() / 2.0 - settings.target().padding + 0.01;
+        for (part, _) in G::parts() {
+            let mut mesh = G::mesh(&settings, part);
+            frame.transform_mesh(&mut mesh, settings.size());
+            if let Some(bounds) = mesh_bounds(mesh) {
+                assert!(
+                    bounds.min.cmpge(-half).all() && bounds.max.cmple(half).all(),
+                    "{} leaves the texture: {:?}",
+                    G::NAME,
+                    bounds
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parts_are_fitted_to_the_texture() {
+        assert_framed::<Kelp>();
+        assert_framed::<Stalk>();
+        assert_framed::<Conifer>();
+        assert_framed::<Leaf>();
+    }
+}
@@ -0,0 +1,418 @@
+use bevy::{
+    core_pipeline::{
+        core_3d::Camera3dBundle,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        camera::{ClearColorConfig, RenderTarget, ScalingMode, Viewport},
+        render_resource::{
+            AsBindGroup, Extent3d, ShaderRef, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages,
+        },
+        view::{NoFrustumCulling, RenderLayers},
+    },
+};
+
+use crate::{components::RenderLayerAllocator, generator::VegetationGenerator};
+
+/// What an impostor capture renders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpostorCapture {
+    /// The unlit plant, the impostor is lit when it is drawn.
+    Color,
+    /// World space normals encoded in `[0, 1]`.
+    Normal,
+    /// Linear depth of the view in `[0, 1]`, 1 being closest to the camera.
+    Depth,
+}
+
+impl ImpostorCapture {
+    pub const ALL: [ImpostorCapture; 3] = [
+        ImpostorCapture::Color,
+        ImpostorCapture::Normal,
+        ImpostorCapture::Depth,
+    ];
+
+    /// Offset of the render layer of the capture relative to the first one.
+    pub fn layer_offset(&self) -> u8 {
+        match self {
+            ImpostorCapture::Color => 0,
+            ImpostorCapture::Normal => 1,
+            ImpostorCapture::Depth => 2,
+        }
+    }
+}
+
+/// Maps a direction on the upper hemisphere to `[-1, 1]²`.
+pub fn hemi_octahedral_encode(dir: Vec3) -> Vec2 {
+    let d = Vec3::new(dir.x, dir.y.max(0.0), dir.z);
+    let p = Vec2::new(d.x, d.z) / (d.x.abs() + d.y.abs() + d.z.abs()).max(f32::EPSILON);
+    Vec2::new(p.x + p.y, p.x - p.y)
+}
+
+/// Inverse of [`hemi_octahedral_encode`].
+pub fn hemi_octahedral_decode(uv: Vec2) -> Vec3 {
+    let p = Vec2::new(uv.x + uv.y, uv.x - uv.y) * 0.5;
+    Vec3::new(p.x, 1.0 - p.x.abs() - p.y.abs(), p.y).normalize()
+}
+
+/// The direction the frame `cell` of a `frames`×`frames` atlas is looking from.
+pub fn frame_direction(cell: UVec2, frames: u32) -> Vec3 {
+    let uv = (cell.as_vec2() + 0.5) / frames as f32 * 2.0 - 1.0;
+    hemi_octahedral_decode(uv)
+}
+
+/// The camera of a frame. Must match the basis used in `impostor.wgsl`.
+fn frame_transform(center: Vec3, dir: Vec3, radius: f32) -> Transform {
+    let up = if dir.y.abs() > 0.999 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    Transform::from_translation(center + dir * radius * 2.0).looking_at(center, up)
+}
+
+/// The textures of an impostor. Every map holds `frames`×`frames` views
+/// arranged on a hemi-octahedral grid.
+#[derive(Debug, Clone)]
+pub struct ImpostorMaps {
+    pub color: Handle<Image>,
+    pub normal: Handle<Image>,
+    pub depth: Handle<Image>,
+    pub frames: u32,
+    /// Radius of the sphere around the captured plant.
+    pub radius: f32,
+}
+
+impl ImpostorMaps {
+    pub fn map(&self, capture: ImpostorCapture) -> &Handle<Image> {
+        match capture {
+            ImpostorCapture::Color => &self.color,
+            ImpostorCapture::Normal => &self.normal,
+            ImpostorCapture::Depth => &self.depth,
+        }
+    }
+}
+
+/// An impostor bake in flight. The cameras and the captured instances are its
+/// children; they are despawned together with it and its render layers are
+/// freed once the atlases have been rendered.
+#[derive(Component)]
+pub struct ImpostorBake {
+    layer: u8,
+    frames_left: u32,
+}
+
+fn target_image(size: u32, format: TextureFormat) -> Image {
+    let size = Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    image
+}
+
+/// Renders the macro mesh of `G` at all [`VegetationGenerator::instances`]
+/// from `frames`×`frames` directions into three atlases, one per
+/// [`ImpostorCapture`]. The captures are drawn on layers taken from the
+/// [`RenderLayerAllocator`], see [`ImpostorBake`]. Returns `None` if all layers
+/// are in use.
+#[allow(clippy::too_many_arguments)]
+pub fn bake_impostor<G: VegetationGenerator>(
+    commands: &mut Commands,
+    images: &mut ResMut<Assets<Image>>,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<G::Material>>,
+    layers: &mut RenderLayerAllocator,
+    settings: &G::Settings,
+    material: &G::Material,
+    frames: u32,
+    frame_size: u32,
+) -> Option<ImpostorMaps> {
+    let Some(layer) = layers.allocate(ImpostorCapture::ALL.len() as u8) else {
+        error!("Cannot bake an impostor, all render layers are in use");
+        return None;
+    };
+
+    let instances = G::instances(settings);
+    let mesh = meshes.add(G::macro_mesh(settings));
+
+    // bounding sphere of the clump; every instance is about as large as its scale
+    let center =
+        instances.iter().map(|t| t.translation).sum::<Vec3>() / instances.len().max(1) as f32;
+    let radius = instances
+        .iter()
+        .map(|t| t.translation.distance(center) + t.scale.max_element())
+        .fold(0.0, f32::max);
+
+    let size = frames * frame_size;
+    let maps = ImpostorMaps {
+        color: images.add(target_image(size, TextureFormat::Rgba8UnormSrgb)),
+        normal: images.add(target_image(size, TextureFormat::Rgba8Unorm)),
+        depth: images.add(target_image(size, TextureFormat::Rgba8Unorm)),
+        frames,
+        radius,
+    };
+
+    commands
+        .spawn((
+            SpatialBundle::default(),
+            ImpostorBake {
+                layer,
+                frames_left: 3,
+            },
+        ))
+        .with_children(|parent| {
+            for capture in ImpostorCapture::ALL {
+                let layers = RenderLayers::layer(layer + capture.layer_offset());
+                if let Some(material) = G::capture_material(material, capture) {
+                    let material = materials.add(material);
+                    for transform in &instances {
+                        parent.spawn((
+                            MaterialMeshBundle {
+                                mesh: mesh.clone(),
+                                transform: *transform,
+                                material: material.clone(),
+                                ..default()
+                            },
+                            NoFrustumCulling,
+                            layers,
+                        ));
+                    }
+                }
+                for i in 0..frames * frames {
+                    let cell = UVec2::new(i % frames, i / frames);
+                    let dir = frame_direction(cell, frames);
+                    parent.spawn((
+                        Camera3dBundle {
+                            camera: Camera {
+                                order: -1000
+                                    + (capture.layer_offset() as u32 * frames * frames + i)
+                                        as isize,
+                                target: RenderTarget::Image(maps.map(capture).clone()),
+                                viewport: Some(Viewport {
+                                    physical_position: cell * frame_size,
+                                    physical_size: UVec2::splat(frame_size),
+                                    ..default()
+                                }),
+                                // the whole target is cleared, so only the first camera may do it
+                                clear_color: if i == 0 {
+                                    ClearColorConfig::Custom(Color::NONE)
+                                } else {
+                                    ClearColorConfig::None
+                                },
+                                ..default()
+                            },
+                            projection: Projection::Orthographic(OrthographicProjection {
+                                scaling_mode: ScalingMode::Fixed {
+                                    width: radius * 2.0,
+                                    height: radius * 2.0,
+                                },
+                                near: 0.0,
+                                far: radius * 4.0,
+                                ..default()
+                            }),
+                            // keep the normals and depths as they are
+                            tonemapping: Tonemapping::None,
+                            dither: DebandDither::Disabled,
+                            transform: frame_transform(center, dir, radius),
+                            ..default()
+                        },
+                        layers,
+                    ));
+                }
+            }
+        });
+
+    Some(maps)
+}
+
+/// Despawns finished impostor bakes and frees their render layers. The
+/// atlases keep their content.
+pub fn finish_impostor_bakes(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut ImpostorBake)>,
+    mut layers: ResMut<RenderLayerAllocator>,
+) {
+    for (entity, mut bake) in query.iter_mut() {
+        if bake.frames_left == 0 {
+            layers.free(bake.layer, ImpostorCapture::ALL.len() as u8);
+            commands.entity(entity).despawn_recursive();
+        } else {
+            bake.frames_left -= 1;
+        }
+    }
+}
+
+/// The capture material of species drawn with a [`StandardMaterial`]: the
+/// unlit plant for the colour, the other captures aren't supported.
+pub fn unlit_capture(
+    material: &StandardMaterial,
+    capture: ImpostorCapture,
+) -> Option<StandardMaterial> {
+    (capture == ImpostorCapture::Color).then(|| StandardMaterial {
+        unlit: true,
+        ..material.clone()
+    })
+}
+
+/// Draws an impostor as a single quad facing the camera, blending the four
+/// frames closest to the view direction.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct ImpostorMaterial {
+    #[uniform(0)]
+    pub radius: f32,
+    #[uniform(0)]
+    pub frames: u32,
+    #[texture(1)]
+    #[sampler(2)]
+    pub color: Handle<Image>,
+    #[texture(3)]
+    #[sampler(4)]
+    pub normal: Handle<Image>,
+}
+
+impl ImpostorMaterial {
+    pub fn new(maps: &ImpostorMaps) -> Self {
+        ImpostorMaterial {
+            radius: maps.radius,
+            frames: maps.frames,
+            color: maps.color.clone(),
+            normal: maps.normal.clone(),
+        }
+    }
+}
+
+impl Material for ImpostorMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/impostor.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/impostor.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Mask(0.5)
+    }
+}
+
+/// The quad of an impostor. Place it at the center of the captured clump.
+pub fn make_impostor_mesh() -> Mesh {
+    Mesh::from(Rectangle::new(2.0, 2.0))
+}
+
+/// Renders impostors and cleans up finished bakes.
+pub struct ImpostorPlugin;
+
+impl Plugin for ImpostorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<ImpostorMaterial> {
+            // the quads are turned towards the camera in the vertex shader
+            prepass_enabled: false,
+            ..default()
+        })
+        .init_resource::<RenderLayerAllocator>()
+        .add_systems(Update, finish_impostor_bakes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Conifer, ConiferSettings};
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn hemi_octahedral_round_trip() {
+        for i in 0..=16 {
+            for j in 0..=16 {
+                let uv = Vec2::new(i as f32, j as f32) / 8.0 - 1.0;
+                // the upper hemisphere only covers the diamond |u| + |v| <= 1
+                if uv.x.abs() + uv.y.abs() > 1.0 {
+                    continue;
+                }
+                let dir = hemi_octahedral_decode(uv);
+                assert!(dir.y >= 0.0);
+                assert!((dir.length() - 1.0).abs() < 1e-5);
+                assert!(hemi_octahedral_encode(dir).distance(uv) < 1e-5, "{uv}");
+            }
+        }
+        assert!(hemi_octahedral_encode(Vec3::Y).length() < 1e-6);
+    }
+
+    #[test]
+    fn finished_bakes_are_despawned_and_free_their_layers() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .init_resource::<RenderLayerAllocator>()
+            .add_systems(Update, finish_impostor_bakes);
+
+        let maps = app
+            .world
+            .run_system_once(
+                |mut commands: Commands,
+                 mut images: ResMut<Assets<Image>>,
+                 mut meshes: ResMut<Assets<Mesh>>,
+                 mut materials: ResMut<Assets<StandardMaterial>>,
+                 mut layers: ResMut<RenderLayerAllocator>| {
+                    bake_impostor::<Conifer>(
+                        &mut commands,
+                        &mut images,
+                        &mut meshes,
+                        &mut materials,
+                        &mut layers,
+                        &ConiferSettings::default(),
+                        &StandardMaterial::default(),
+                        4,
+                        16,
+                    )
+                },
+            )
+            .unwrap();
+        assert_eq!(maps.frames, 4);
+        assert!(maps.radius > 0.0);
+
+        let mut cameras = app.world.query::<&Camera>();
+        assert_eq!(cameras.iter(&app.world).count(), 3 * 4 * 4);
+        // conifers only capture their colour
+        let mut instances = app
+            .world
+            .query_filtered::<&RenderLayers, With<Handle<Mesh>>>();
+        assert!(instances
+            .iter(&app.world)
+            .all(|l| *l == RenderLayers::layer(2)));
+        assert!(instances.iter(&app.world).count() > 0);
+
+        for _ in 0..4 {
+            app.update();
+        }
+        assert_eq!(cameras.iter(&app.world).count(), 0);
+        assert_eq!(instances.iter(&app.world).count(), 0);
+        let mut layers = app.world.resource_mut::<RenderLayerAllocator>();
+        assert_eq!(layers.allocate(3), Some(2));
+        // the atlases are kept
+        assert!(app.world.resource::<Assets<Image>>().contains(&maps.color));
+    }
+}
@@ -0,0 +1,192 @@
+use bevy::{pbr::ExtendedMaterial, prelude::*};
+use bevy_procedural_meshes::{lyon::PFill, *};
+use std::f32::consts::PI;
+
+use crate::{
+    components::{make_kelp_material, make_kelp_mesh, Kelp, KelpMaterial, KelpSettings},
+    draw::polygon,
+    generator::VegetationGenerator,
+    impostor::{unlit_capture, ImpostorCapture},
+};
+
+#[derive(Debug, Reflect, Component, PartialEq)]
+pub enum KelpPart {
+    Stipe,
+    Blade,
+    Bladder,
+    Holdfast,
+}
+
+pub fn kelp_mesh(settings: &KelpSettings, part: KelpPart) -> PMesh<u16> {
+    let mut fill = PFill::new(0.0001);
+    fill.draw(|builder| {
+        let stipe_w = settings.stipe_w;
+        // the roots of the holdfast need some room at the start of the texture
+        let root = Vec2::new(settings.holdfast_len, 0.0);
+
+        if part == KelpPart::Stipe {
+            polygon(
+                builder,
+                &[
+                    root + Vec2::new(0.0, stipe_w),
+                    Vec2::new(1.0, stipe_w * 0.5),
+                    Vec2::new(1.0, -stipe_w * 0.5),
+                    root - Vec2::new(0.0, stipe_w),
+                ],
+            );
+        }
+
+        if part == KelpPart::Holdfast {
+            // a fan of tapering roots gripping the rock below the stipe
+            let roots = settings.holdfast_roots.max(1);
+            for j in 0..roots {
+                let a = PI + (j as f32 / (roots - 1).max(1) as f32 - 0.5) * PI * 0.8;
+                let d = Vec2::new(a.cos(), a.sin());
+                let n = Vec2::new(-d.y, d.x);
+                let l = settings.holdfast_len * (1.0 - 0.3 * (j as f32 * 1.7).sin().abs());
+                let kink = root + d * l * 0.5 + n * l * 0.1 * (j as f32 * 2.3).sin();
+                polygon(
+                    builder,
+                    &[
+                        root + n * stipe_w * 0.5,
+                        kink + n * stipe_w * 0.3,
+                        root + d * l,
+                        kink - n * stipe_w * 0.3,
+                        root - n * stipe_w * 0.5,
+                    ],
+                );
+            }
+        }
+
+        let blades = settings.blades;
+        for i in 0..blades {
+            let prog = i as f32 / blades as f32;
+            let dir = ((i % 2) * 2) as f32 - 1.0;
+            let x = root.x + 0.05 + (0.9 - root.x) * prog;
+            let base = Vec2::new(x, dir * stipe_w * (1.0 - 0.5 * prog));
+            let a = settings.blade_angle * dir;
+            let d = Vec2::new(a.cos(), a.sin());
+            let n = Vec2::new(-d.y, d.x);
+
+            // the gas bladder sits between the stipe and the blade
+            let r = settings.bladder_size;
+            if part == KelpPart::Bladder && r > 0.0 {
+                let center = base + d * r;
+                let points: Vec<Vec2> = (0..16)
+                    .map(|k| {
+                        let t = k as f32 / 16.0 * 2.0 * PI;
+                        center + d * t.cos() * r + n * t.sin() * r * 0.6
+                    })
+                    .collect();
+                polygon(builder, &points);
+            }
+
+            if part == KelpPart::Stipe && r > 0.0 {
+                // the short stalk connecting the bladder to the stipe
+                let stalk_w = stipe_w * 0.15;
+                polygon(
+                    builder,
+                    &[
+                        base + n * stalk_w,
+                        base + d * r + n * stalk_w,
+                        base + d * r - n * stalk_w,
+                        base - n * stalk_w,
+                    ],
+                );
+            }
+
+            if part == KelpPart::Blade {
+                // a long strap with ruffled edges that tapers at both ends
+                let start = base + d * 2.0 * r;
+                let l = settings.blade_len * (1.0 - 0.5 * prog);
+                let segments = 32;
+                let mut left = Vec::with_capacity(segments + 1);
+                let mut right = Vec::with_capacity(segments + 1);
+                for k in 0..=segments {
+                    let t = k as f32 / segments as f32;
+                    // sin(PI) is slightly negative in f32, which powf turns into NaN
+                    let half = settings.blade_w * (PI * t).sin().max(0.0).powf(0.6) * 0.5;
+                    let ruffle =
+                        settings.ruffle_amp * (t * settings.ruffles as f32 * 2.0 * PI).sin();
+                    left.push(start + d * t * l + n * (half + ruffle));
+                    right.push(start + d * t * l - n * (half - ruffle));
+                }
+                right.reverse();
+                left.append(&mut right);
+                polygon(builder, &left);
+            }
+        }
+    });
+    fill.build()
+}
+
+impl VegetationGenerator for Kelp {
+    type Settings = KelpSettings;
+    type Part = KelpPart;
+    type Material = ExtendedMaterial<StandardMaterial, KelpMaterial>;
+    const NAME: &'static str = "kelp";
+    const VERSION: u32 = 1;
+
+    fn parts() -> Vec<(KelpPart, f32)> {
+        // blades and holdfast behind the stipe, bladders on top of it
+        vec![
+            (KelpPart::Stipe, 0.0),
+            (KelpPart::Blade, -1.0),
+            (KelpPart::Bladder, 1.0),
+            (KelpPart::Holdfast, -1.0),
+        ]
+    }
+
+    fn mesh(settings: &KelpSettings, part: KelpPart) -> PMesh<u16> {
+        kelp_mesh(settings, part)
+    }
+
+    fn palette() -> Vec<Color> {
+        vec![
+            Color::rgb(0.25, 0.2, 0.05),
+            Color::rgb(0.35, 0.3, 0.05),
+            Color::rgb(0.4, 0.35, 0.1),
+            Color::rgb(0.2, 0.15, 0.05),
+        ]
+    }
+
+    fn macro_mesh(settings: &KelpSettings) -> Mesh {
+        make_kelp_mesh(settings.fronds)
+    }
+
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material {
+        let settings = KelpSettings::default();
+        make_kelp_material(color, normal, settings.depth, settings.current)
+    }
+
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>) {
+        (
+            &mut material.base.base_color_texture,
+            &mut material.base.normal_map_texture,
+        )
+    }
+
+    fn capture_material(
+        material: &Self::Material,
+        capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        unlit_capture(&material.base, capture).map(|base| ExtendedMaterial {
+            base,
+            extension: material.extension.clone(),
+        })
+    }
+
+    fn apply_settings(
+        settings: &KelpSettings,
+        material: &mut Self::Material,
+        _thickness: Option<Handle<Image>>,
+    ) {
+        material.extension.fronds = settings.fronds;
+        material.extension.frond_width = settings.frond_w;
+        material.extension.depth = settings.depth;
+        material.extension.current = settings.current;
+        material.extension.buoyancy = settings.buoyancy;
+    }
+}
@@ -0,0 +1,265 @@
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use std::{io, path::Path};
+
+use crate::bake::generate_mips;
+
+/// The file identifier of KTX 2.0.
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+/// Turns the RGBA8 mip levels of a map into the payload of a KTX2 file, e.g.,
+/// by block compressing or supercompressing them.
+pub trait Ktx2Encoder {
+    /// The `VkFormat` of the encoded levels.
+    fn vk_format(&self, srgb: bool) -> u32;
+
+    /// The size in bytes of the type the data is made of, 1 for block compressed formats.
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    /// 0 for none, 1 for `BasisLZ`, 2 for Zstandard and 3 for ZLIB.
+    fn supercompression_scheme(&self) -> u32 {
+        0
+    }
+
+    /// Size of a level of `width`×`height` texels before supercompression.
+    fn uncompressed_level_size(&self, width: u32, height: u32) -> usize {
+        (width * height * 4) as usize
+    }
+
+    /// The data format descriptor block describing the encoded texels.
+    fn data_format_descriptor(&self, srgb: bool) -> Vec<u8> {
+        rgba8_descriptor(srgb)
+    }
+
+    /// Encodes a single level given as tightly packed RGBA8 texels.
+    fn encode_level(&self, rgba: &[u8], width: u32, height: u32) -> Vec<u8>;
+}
+
+/// Stores the texels as they are. Bevy loads these with its `ktx2` feature.
+pub struct UncompressedKtx2;
+
+impl Ktx2Encoder for UncompressedKtx2 {
+    fn vk_format(&self, srgb: bool) -> u32 {
+        if srgb {
+            VK_FORMAT_R8G8B8A8_SRGB
+        } else {
+            VK_FORMAT_R8G8B8A8_UNORM
+        }
+    }
+
+    fn encode_level(&self, rgba: &[u8], _width: u32, _height: u32) -> Vec<u8> {
+        rgba.to_vec()
+    }
+}
+
+/// The basic data format descriptor block of straight alpha RGBA8.
+fn rgba8_descriptor(srgb: bool) -> Vec<u8> {
+    let mut block = vec![];
+    let mut word = |w: u32| block.extend_from_slice(&w.to_le_bytes());
+    // vendor and descriptor type: Khronos, basic
+    word(0);
+    // version 2 and the size of the block with four samples
+    word(2 | ((24 + 16 * 4) << 16));
+    // RGBSDA, BT.709 primaries, the transfer function and straight alpha
+    word(1 | (1 << 8) | ((if srgb { 2 } else { 1 }) << 16));
+    // 1x1 texel blocks of 4 bytes
+    word(0);
+    word(4);
+    word(0);
+    for (i, channel) in [0u32, 1, 2, 15].into_iter().enumerate() {
+        // alpha is never sRGB encoded
+        let linear = if srgb && channel == 15 { 0x10 } else { 0 };
+        word((i as u32 * 8) | (7 << 16) | ((channel | linear) << 24));
+        word(0);
+        word(0);
+        word(255);
+    }
+    block
+}
+
+/// The RGBA8 texels of every mip level of an 8 bit image, largest first.
+fn rgba8_levels(img: &Image) -> Vec<(Vec<u8>, u32, u32)> {
+    let bgra = matches!(
+        img.texture_descriptor.format,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+    );
+    let (mut width, mut height) = (img.width(), img.height());
+    let mut offset = 0;
+    let mut levels = vec![];
+    for _ in 0..img.texture_descriptor.mip_level_count {
+        let len = (width * height * 4) as usize;
+        let Some(data) = img.data.get(offset..offset + len) else {
+            break;
+        };
+        let mut data = data.to_vec();
+        if bgra {
+            for p in data.chunks_exact_mut(4) {
+                p.swap(0, 2);
+            }
+        }
+        levels.push((data, width, height));
+        offset += len;
+        (width, height) = ((width / 2).max(1), (height / 2).max(1));
+    }
+    levels
+}
+
+/// Encodes a map with its full mip chain as KTX2. Images without mips get a
+/// box filtered chain, see [`generate_mips`].
+pub fn ktx2_data(img: &Image, encoder: &dyn Ktx2Encoder) -> Vec<u8> {
+    let srgb = img.texture_descriptor.format.is_srgb();
+    let levels = if img.texture_descriptor.mip_level_count > 1 {
+        rgba8_levels(img)
+    } else {
+        rgba8_levels(&generate_mips(img, None))
+    };
+    let encoded: Vec<Vec<u8>> = levels
+        .iter()
+        .map(|(data, width, height)| encoder.encode_level(data, *width, *height))
+        .collect();
+    let supercompression = encoder.supercompression_scheme();
+
+    let descriptor = encoder.data_format_descriptor(srgb);
+    let level_index = 80;
+    let dfd_offset = level_index + 24 * encoded.len();
+    let dfd_length = 4 + descriptor.len();
+    // the levels are stored from the smallest to the largest
+    let mut offsets = vec![0; encoded.len()];
+    let mut end = dfd_offset + dfd_length;
+    for (i, level) in encoded.iter().enumerate().rev() {
+        if supercompression == 0 {
+            end = end.next_multiple_of(4);
+        }
+        offsets[i] = end;
+        end += level.len();
+    }
+
+    let mut out = Vec::with_capacity(end);
+    out.extend_from_slice(&IDENTIFIER);
+    let u32s = |out: &mut Vec<u8>, values: &[u32]| {
+        for v in values {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    };
+    u32s(
+        &mut out,
+        &[
+            encoder.vk_format(srgb),
+            encoder.type_size(),
+            img.width(),
+            img.height(),
+            0,
+            0,
+            1,
+            encoded.len() as u32,
+            supercompression,
+            dfd_offset as u32,
+            dfd_length as u32,
+            // no key/value data
+            0,
+            0,
+        ],
+    );
+    // no supercompression global data
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    for ((level, (_, width, height)), offset) in encoded.iter().zip(&levels).zip(&offsets) {
+        out.extend_from_slice(&(*offset as u64).to_le_bytes());
+        out.extend_from_slice(&(level.len() as u64).to_le_bytes());
+        let uncompressed = if supercompression == 0 {
+            level.len()
+        } else {
+            encoder.uncompressed_level_size(*width, *height)
+        };
+        out.extend_from_slice(&(uncompressed as u64).to_le_bytes());
+    }
+    u32s(&mut out, &[dfd_length as u32]);
+    out.extend_from_slice(&descriptor);
+    for (level, offset) in encoded.iter().zip(&offsets).rev() {
+        out.resize(*offset, 0);
+        out.extend_from_slice(level);
+    }
+    out
+}
+
+/// Writes a map with its full mip chain to a KTX2 file.
+pub fn export_ktx2(img: &Image, path: &Path, encoder: &dyn Ktx2Encoder) -> io::Result<()> {
+    std::fs::write(path, ktx2_data(img, encoder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_images::{filled, image};
+    use ktx2::{
+        BasicDataFormatDescriptor, ChannelTypeQualifiers, Format, Reader, TransferFunction,
+    };
+
+    #[test]
+    fn levels_are_aligned_and_indexed() {
+        let img = image(UVec2::new(8, 4), TextureFormat::Rgba8UnormSrgb, |x, y| {
+            [x as u8 * 31, y as u8 * 17, (x * y) as u8, 255]
+        });
+        let data = ktx2_data(&img, &UncompressedKtx2);
+        let reader = Reader::new(data.as_slice()).unwrap();
+
+        let header = reader.header();
+        assert_eq!(header.format, Some(Format::R8G8B8A8_SRGB));
+        assert_eq!((header.pixel_width, header.pixel_height), (8, 4));
+        assert_eq!(header.level_count, 4);
+        assert_eq!(header.supercompression_scheme, None);
+
+        let mut previous = data.len();
+        for (i, level) in reader.levels().enumerate() {
+            let entry = &data[80 + 24 * i..][..24];
+            let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+            let length = u64::from_le_bytes(entry[8..16].try_into().unwrap()) as usize;
+            let (width, height) = ((8 >> i).max(1), (4 >> i).max(1));
+            assert_eq!(offset % 4, 0);
+            assert_eq!(length, width * height * 4);
+            assert_eq!(level.len(), length);
+            // the smallest level comes first
+            assert!(offset + length <= previous);
+            previous = offset;
+        }
+        assert_eq!(reader.levels().next(), Some(img.data.as_slice()));
+    }
+
+    #[test]
+    fn descriptor_describes_srgb_rgba8() {
+        let data = ktx2_data(
+            &filled(UVec2::splat(2), TextureFormat::Rgba8UnormSrgb, [255; 4]),
+            &UncompressedKtx2,
+        );
+        let reader = Reader::new(data.as_slice()).unwrap();
+        let descriptors: Vec<_> = reader.data_format_descriptors().collect();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].header.vendor_id, 0);
+        assert_eq!(descriptors[0].header.descriptor_type, 0);
+        assert_eq!(descriptors[0].header.version_number, 2);
+
+        let basic = BasicDataFormatDescriptor::parse(descriptors[0].data).unwrap();
+        assert_eq!(basic.transfer_function, Some(TransferFunction::SRGB));
+        assert_eq!(basic.texel_block_dimensions, [1; 4]);
+        assert_eq!(basic.bytes_planes[0], 4);
+        let samples: Vec<_> = basic.sample_information().collect();
+        assert_eq!(samples.len(), 4);
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(sample.bit_offset, i as u32 * 8);
+            assert_eq!(sample.bit_length, 8);
+            // only the alpha is stored linearly
+            assert_eq!(
+                sample
+                    .channel_type_qualifiers
+                    .contains(ChannelTypeQualifiers::LINEAR),
+                i == 3
+            );
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use bevy::prelude::*;
+use bevy_procedural_meshes::{lyon::PFill, *};
+
+use crate::{
+    bake::{blur, height_field, normal_from_height},
+    components::{make_card_material, make_card_mesh, Leaf, LeafSettings},
+    draw::{along, hash, polygon, stroke},
+    generator::VegetationGenerator,
+    impostor::{unlit_capture, ImpostorCapture},
+};
+
+#[derive(Debug, Reflect, Component, PartialEq)]
+pub enum LeafPart {
+    Petiole,
+    Blade,
+    Midrib,
+    SecondaryVeins,
+    TertiaryVeins,
+}
+
+/// Palette to bake the vein mask instead of the colour texture. The blade is
+/// black and the veins get brighter the more important they are. Also used as
+/// the height map of the normal map.
+pub const VEIN_MASK: [Color; 5] = [
+    Color::rgb(1.0, 1.0, 1.0),
+    Color::rgb(0.0, 0.0, 0.0),
+    Color::rgb(1.0, 1.0, 1.0),
+    Color::rgb(0.7, 0.7, 0.7),
+    Color::rgb(0.4, 0.4, 0.4),
+];
+
+/// Half width of the blade at `x` in leaf space.
+fn blade_width(settings: &LeafSettings, x: f32) -> f32 {
+    let t = (x - settings.petiole_len) / (1.0 - settings.petiole_len);
+    settings.blade_w * 0.5 * settings.shape.half_width(t)
+}
+
+/// The secondary veins as polylines. They leave the midrib at `secondary_angle`
+/// and curve towards the tip until they get close to the margin.
+fn secondary_veins(settings: &LeafSettings) -> Vec<Vec<Vec2>> {
+    let n = settings.secondary;
+    let blade_len = 1.0 - settings.petiole_len;
+    let mut veins = Vec::with_capacity(2 * n as usize);
+    for side in [-1.0, 1.0] {
+        for i in 0..n {
+            let offset = if side > 0.0 { settings.alternate } else { 0.0 };
+            let t = (i as f32 + 0.5 + offset) / (n as f32 + 1.0);
+            let mut p = Vec2::new(settings.petiole_len + t * blade_len, 0.0);
+            let mut vein = vec![p];
+            let segments = 8;
+            let step = blade_len / n as f32;
+            for k in 0..segments {
+                let s = k as f32 / segments as f32;
+                let a = settings.secondary_angle * (1.0 - settings.secondary_curve * s);
+                let next = p + Vec2::new(a.cos(), side * a.sin()) * step / segments as f32 * 4.0;
+                if next.x >= 1.0 || next.y.abs() > 0.9 * blade_width(settings, next.x) {
+                    break;
+                }
+                p = next;
+                vein.push(p);
+            }
+            veins.push(vein);
+        }
+    }
+    veins
+}
+
+pub fn leaf_mesh(settings: &LeafSettings, part: LeafPart) -> PMesh<u16> {
+    let mut fill = PFill::new(0.0001);
+    fill.draw(|builder| {
+        let base = Vec2::new(settings.petiole_len, 0.0);
+
+        if part == LeafPart::Petiole && settings.petiole_len > 0.0 {
+            stroke(
+                builder,
+                &[Vec2::ZERO, base],
+                settings.petiole_w,
+                settings.midrib_w,
+            );
+        }
+
+        if part == LeafPart::Blade {
+            polygon(
+                builder,
+                &settings.shape.outline(
+                    base,
+                    Vec2::X,
+                    1.0 - settings.petiole_len,
+                    settings.blade_w,
+                    48,
+                ),
+            );
+        }
+
+        if part == LeafPart::Midrib {
+            stroke(
+                builder,
+                &[base, Vec2::new(1.0, 0.0)],
+                settings.midrib_w,
+                settings.midrib_w * 0.2,
+            );
+        }
+
+        let secondary = secondary_veins(settings);
+
+        if part == LeafPart::SecondaryVeins {
+            for vein in secondary.iter() {
+                stroke(
+                    builder,
+                    vein,
+                    settings.secondary_w,
+                    settings.secondary_w * 0.2,
+                );
+            }
+        }
+
+        if part == LeafPart::TertiaryVeins && settings.tertiary > 0 {
+            // percurrent veins connecting neighbouring secondary veins
+            let n = settings.secondary as usize;
+            for (j, pair) in secondary.windows(2).enumerate() {
+                if (j + 1) % n == 0 || pair[0].len() < 2 || pair[1].len() < 2 {
+                    continue;
+                }
+                for k in 0..settings.tertiary {
+                    let f = (k as f32 + 0.5) / settings.tertiary as f32;
+                    let a = along(&pair[0], f);
+                    let b = along(&pair[1], f);
+                    let wobble = (hash(j as u32, k) - 0.5) * 0.3 * a.distance(b);
+                    let mid = a.lerp(b, 0.5) + Vec2::new(wobble, 0.0);
+                    stroke(
+                        builder,
+                        &[a, mid, b],
+                        settings.tertiary_w,
+                        settings.tertiary_w,
+                    );
+                }
+            }
+        }
+    });
+    fill.build()
+}
+
+impl VegetationGenerator for Leaf {
+    type Settings = LeafSettings;
+    type Part = LeafPart;
+    type Material = StandardMaterial;
+    const NAME: &'static str = "leaf";
+    const VERSION: u32 = 1;
+
+    fn parts() -> Vec<(LeafPart, f32)> {
+        // the blade behind the petiole, the important veins on top
+        vec![
+            (LeafPart::Petiole, 0.0),
+            (LeafPart::Blade, -1.0),
+            (LeafPart::Midrib, 3.0),
+            (LeafPart::SecondaryVeins, 2.0),
+            (LeafPart::TertiaryVeins, 1.0),
+        ]
+    }
+
+    fn mesh(settings: &LeafSettings, part: LeafPart) -> PMesh<u16> {
+        leaf_mesh(settings, part)
+    }
+
+    fn palette() -> Vec<Color> {
+        vec![
+            Color::rgb(0.2, 0.3, 0.05),
+            Color::rgb(0.1, 0.35, 0.05),
+            Color::rgb(0.25, 0.4, 0.1),
+            Color::rgb(0.15, 0.4, 0.08),
+            Color::rgb(0.12, 0.38, 0.06),
+        ]
+    }
+
+    fn height_palette() -> Vec<Color> {
+        VEIN_MASK.to_vec()
+    }
+
+    fn normal_map(_settings: &LeafSettings, height: &Image) -> Option<Image> {
+        let radius = (height.width() / 512).max(1) as usize;
+        let field = blur(&height_field(height), height.width() as usize, radius);
+        Some(normal_from_height(
+            &field,
+            height.width(),
+            height.height(),
+            4.0,
+        ))
+    }
+
+    fn macro_mesh(_settings: &LeafSettings) -> Mesh {
+        make_card_mesh()
+    }
+
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material {
+        make_card_material(color, normal)
+    }
+
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>) {
+        (
+            &mut material.base_color_texture,
+            &mut material.normal_map_texture,
+        )
+    }
+
+    fn capture_material(
+        material: &Self::Material,
+        capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        unlit_capture(material, capture)
+    }
+}
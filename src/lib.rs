@@ -1,26 +1,20 @@
-use bevy::prelude::*;
-use components::FernSettings;
-use fern::{fern_mesh, FernPart};
+pub mod atlas;
+pub mod bake;
+pub mod cache;
 pub mod components;
+pub mod conifer;
+mod draw;
+pub mod export;
 pub mod fern;
-
-#[no_mangle]
-pub fn update_vegetation(
-    query: Query<&FernSettings, Changed<FernSettings>>,
-    mut assets: ResMut<Assets<Mesh>>,
-) {
-    for settings in query.iter() {
-        //println!("Updating fern mesh");
-        let fern = fern_mesh(settings, FernPart::Stem);
-        let mesh = assets.get_mut(settings.meshes[0]).unwrap();
-        fern.bevy_set(mesh);
-
-        let fern = fern_mesh(settings, FernPart::LeafletTop);
-        let mesh = assets.get_mut(settings.meshes[1]).unwrap();
-        fern.bevy_set(mesh);
-
-        let fern = fern_mesh(settings, FernPart::LeafletBottom);
-        let mesh = assets.get_mut(settings.meshes[2]).unwrap();
-        fern.bevy_set(mesh);
-    }
-}
+pub mod generator;
+pub mod impostor;
+pub mod kelp;
+pub mod ktx2;
+pub mod leaf;
+pub mod palette;
+pub mod raster;
+pub mod season;
+pub mod shapes;
+pub mod stalk;
+#[cfg(test)]
+mod test_images;
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::draw::hash;
+
+/// Where a point of a part lies on the plant, see [`crate::generator::VegetationGenerator::palette_coords`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PaletteCoords {
+    /// From the base (0) to the tip (1) of the rachis.
+    pub along_rachis: f32,
+    /// From the base (0) to the tip (1) of the leaflet.
+    pub along_leaflet: f32,
+    /// Index of the leaflet. Drives the random colour variation.
+    pub leaflet: u32,
+}
+
+/// Colour of a part in the bake. Supports gradients along the rachis and
+/// each leaflet, random variation per leaflet and brown tips.
+#[derive(Debug, Clone, Reflect, PartialEq)]
+pub struct Palette {
+    /// Colour at the base of the rachis.
+    pub base: Color,
+    /// Colour at the tip of the rachis.
+    pub tip: Color,
+    /// Colour at the tip of every leaflet.
+    pub leaflet_tip: Color,
+    /// How far the leaflets blend towards `leaflet_tip`.
+    pub leaflet_gradient: f32,
+    /// Random hue shift per leaflet in degrees.
+    pub hue_jitter: f32,
+    /// Random lightness change per leaflet.
+    pub value_jitter: f32,
+    /// Fraction of the leaflet length at the tip that turns brown.
+    pub browning: f32,
+    /// Colour of the brown tips.
+    pub brown: Color,
+    pub seed: u32,
+}
+
+impl Palette {
+    /// A single colour without any variation.
+    pub fn flat(color: Color) -> Self {
+        Palette {
+            base: color,
+            tip: color,
+            leaflet_tip: color,
+            leaflet_gradient: 0.0,
+            hue_jitter: 0.0,
+            value_jitter: 0.0,
+            browning: 0.0,
+            brown: Color::rgb(0.3, 0.18, 0.05),
+            seed: 0,
+        }
+    }
+
+    /// Whether the palette paints the whole part in one colour.
+    pub fn is_flat(&self) -> bool {
+        self.base == self.tip
+            && (self.leaflet_gradient == 0.0 || self.base == self.leaflet_tip)
+            && self.hue_jitter == 0.0
+            && self.value_jitter == 0.0
+            && self.browning == 0.0
+    }
+
+    /// The colour at the given point of the plant.
+    pub fn eval(&self, coords: PaletteCoords) -> Color {
+        let lerp = |a: Color, b: Color, t: f32| {
+            let a = Vec4::from(a.as_linear_rgba_f32());
+            let b = Vec4::from(b.as_linear_rgba_f32());
+            let c = a.lerp(b, t.clamp(0.0, 1.0));
+            Color::rgba_linear(c.x, c.y, c.z, c.w)
+        };
+
+        let mut color = lerp(self.base, self.tip, coords.along_rachis);
+        color = lerp(
+            color,
+            self.leaflet_tip,
+            coords.along_leaflet * self.leaflet_gradient,
+        );
+
+        if self.hue_jitter != 0.0 || self.value_jitter != 0.0 {
+            let dh = (hash(self.seed, coords.leaflet) * 2.0 - 1.0) * self.hue_jitter;
+            let dl =
+                (hash(self.seed.wrapping_add(1), coords.leaflet) * 2.0 - 1.0) * self.value_jitter;
+            let mut hsla = color.as_hsla();
+            let (h, l) = (hsla.h(), hsla.l());
+            hsla.set_h((h + dh).rem_euclid(360.0))
+                .set_l((l + dl).clamp(0.0, 1.0));
+            color = hsla;
+        }
+
+        if self.browning > 0.0 {
+            let t =
+                ((coords.along_leaflet - (1.0 - self.browning)) / self.browning).clamp(0.0, 1.0);
+            color = lerp(color, self.brown, t * t * (3.0 - 2.0 * t));
+        }
+
+        color
+    }
+}
+
+impl From<Color> for Palette {
+    fn from(color: Color) -> Self {
+        Palette::flat(color)
+    }
+}
+
+/// The palettes of the parts of a bake in the order of
+/// [`crate::generator::VegetationGenerator::parts`]. Added to the settings entity.
+#[derive(Component, Debug, Clone, Reflect, Default)]
+#[reflect(Component)]
+pub struct BakePalette(pub Vec<Palette>);
@@ -0,0 +1,346 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use bevy_procedural_meshes::*;
+
+use crate::{
+    generator::{derive_maps, triangle_leaflets, VegetationGenerator, VegetationSettings},
+    palette::Palette,
+};
+
+/// Triangles of a 2d mesh in pixel coordinates with the origin in the center of the image.
+fn triangles(mesh: PMesh<u16>) -> Vec<[Vec2; 3]> {
+    let mesh = mesh.to_bevy(RenderAssetUsages::all());
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return vec![];
+    };
+    // `to_bevy` duplicates the vertices to compute flat normals, so meshes
+    // without indices list every triangle's vertices in order
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+    indices
+        .chunks_exact(3)
+        .map(|t| [0, 1, 2].map(|k| Vec2::new(positions[t[k]][0], positions[t[k]][1])))
+        .collect()
+}
+
+/// Calls `covered` with the index of the triangle, the index of the pixel and
+/// the sub-samples of the pixel covered by the triangle. The image is sampled
+/// on a regular `samples`×`samples` grid per pixel.
+fn cover(
+    triangles: &[[Vec2; 3]],
+    width: u32,
+    height: u32,
+    samples: u32,
+    mut covered: impl FnMut(usize, usize, u32),
+) {
+    let (w, h) = (width as i32, height as i32);
+    let edge = |a: Vec2, b: Vec2, p: Vec2| (b - a).perp_dot(p - a);
+
+    for (t, &[a, b, c]) in triangles.iter().enumerate() {
+        // from the 2d camera's coordinates to pixels with rows going downwards
+        let [a, b, c] = [a, b, c].map(|v| Vec2::new(v.x + w as f32 / 2.0, h as f32 / 2.0 - v.y));
+        let area = edge(a, b, c);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let min = a.min(b).min(c).floor().max(Vec2::ZERO);
+        let max = a.max(b).max(c).ceil().min(Vec2::new(w as f32, h as f32));
+        for y in min.y as i32..max.y as i32 {
+            for x in min.x as i32..max.x as i32 {
+                let mut bits = 0u32;
+                for s in 0..samples * samples {
+                    let p = Vec2::new(
+                        x as f32 + ((s % samples) as f32 + 0.5) / samples as f32,
+                        y as f32 + ((s / samples) as f32 + 0.5) / samples as f32,
+                    );
+                    let w0 = edge(b, c, p) * area.signum();
+                    let w1 = edge(c, a, p) * area.signum();
+                    let w2 = edge(a, b, p) * area.signum();
+                    if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
+                        bits |= 1 << s;
+                    }
+                }
+                if bits != 0 {
+                    covered(t, (y * w + x) as usize, bits);
+                }
+            }
+        }
+    }
+}
+
+/// Renders the parts of a plant into an image on the CPU. Equivalent to
+/// [`crate::components::render_texture`] but doesn't need a GPU. Edges are
+/// anti-aliased using `samples`×`samples` sub-samples per pixel (at most 5).
+/// Parts without a colour are drawn white.
+pub fn rasterize<G: VegetationGenerator>(
+    settings: &G::Settings,
+    colors: &[Color],
+    samples: u32,
+) -> Image {
+    let palettes: Vec<Palette> = colors.iter().copied().map(Palette::flat).collect();
+    rasterize_with::<G>(settings, &palettes, samples, false)
+}
+
+/// Like [`rasterize`] but evaluates the palettes of the parts at every pixel
+/// and applies [`VegetationGenerator::seasonal_color`].
+pub fn rasterize_palettes<G: VegetationGenerator>(
+    settings: &G::Settings,
+    palettes: &[Palette],
+    samples: u32,
+) -> Image {
+    rasterize_with::<G>(settings, palettes, samples, true)
+}
+
+fn rasterize_with<G: VegetationGenerator>(
+    settings: &G::Settings,
+    palettes: &[Palette],
+    samples: u32,
+    seasonal: bool,
+) -> Image {
+    let size = settings.size();
+    let samples = samples.clamp(1, 5);
+    let n = (samples * samples) as f32;
+    let frame = G::frame(settings);
+    let mut pixels = vec![Vec4::ZERO; (size.x * size.y) as usize];
+
+    // draw the parts back to front
+    let mut parts: Vec<(usize, (G::Part, f32))> = G::parts().into_iter().enumerate().collect();
+    parts.sort_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b));
+
+    let white = Palette::flat(Color::WHITE);
+    if palettes.len() < parts.len() {
+        warn!(
+            "{} palettes given for {} parts of {}, drawing the rest white",
+            palettes.len(),
+            parts.len(),
+            G::NAME
+        );
+    }
+
+    for (i, (part, _)) in parts {
+        let palette = palettes.get(i).unwrap_or(&white);
+        let flat = Vec4::from(palette.base.as_linear_rgba_f32());
+        let (mut mesh, leaflets) = G::mesh_with_leaflets(settings, part);
+        frame.transform_mesh(&mut mesh, size);
+        let leaflets = triangle_leaflets(&mesh, &leaflets);
+        // the leaflet of every pixel is the one of the last triangle covering it
+        let mut mask = vec![0u32; pixels.len()];
+        let mut pixel_leaflets = vec![0u32; pixels.len()];
+        cover(&triangles(mesh), size.x, size.y, samples, |t, k, bits| {
+            mask[k] |= bits;
+            pixel_leaflets[k] = leaflets.get(t).copied().unwrap_or(0);
+        });
+        for (k, (pixel, bits)) in pixels.iter_mut().zip(mask).enumerate() {
+            if bits == 0 {
+                continue;
+            }
+            let color = if palette.is_flat() && !seasonal {
+                flat
+            } else {
+                // the pixel center in the coordinates of the plant
+                let uv = Vec2::new(
+                    ((k as u32 % size.x) as f32 + 0.5) / size.x as f32,
+                    ((k as u32 / size.x) as f32 + 0.5) / size.y as f32,
+                );
+                let coords = G::palette_coords(settings, frame.uv_to_world(uv), pixel_leaflets[k]);
+                let mut color = palette.eval(coords);
+                if seasonal {
+                    color = G::seasonal_color(settings, coords, color);
+                }
+                Vec4::from(color.as_linear_rgba_f32())
+            };
+            let alpha = color.w * bits.count_ones() as f32 / n;
+            let rgb = color.truncate() * alpha + pixel.truncate() * (1.0 - alpha);
+            *pixel = rgb.extend(alpha + pixel.w * (1.0 - alpha));
+        }
+    }
+
+    let data = pixels
+        .into_iter()
+        .flat_map(|p| {
+            // un-premultiply and encode as sRGB
+            let rgb = if p.w > 0.0 {
+                p.truncate() / p.w
+            } else {
+                Vec3::ZERO
+            };
+            Color::rgba_linear(rgb.x, rgb.y, rgb.z, p.w)
+                .as_rgba_f32()
+                .map(|c| (c * 255.0).round() as u8)
+        })
+        .collect();
+
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Derives all maps of a bake from the colour map rendered on the GPU. The
+/// height map is rasterized on the CPU with `samples`×`samples` sub-samples, as
+/// render-to-texture can only render a single pass at a time and only captures layer 1.
+pub fn bake_from_color<G: VegetationGenerator>(
+    settings: &G::Settings,
+    color: Image,
+    samples: u32,
+) -> Vec<(&'static str, Image)> {
+    let heights = G::height_palette();
+    let height = (!heights.is_empty()).then(|| rasterize::<G>(settings, &heights, samples));
+    derive_maps::<G>(settings, color, height)
+}
+
+/// Bakes all maps of a plant on the CPU. The maps are named like the ones
+/// of [`crate::export::Baked`] and can be passed to [`crate::export::export_maps`].
+pub fn bake_headless<G: VegetationGenerator>(
+    settings: &G::Settings,
+    samples: u32,
+) -> Vec<(&'static str, Image)> {
+    let color = rasterize_palettes::<G>(settings, &G::palettes(), samples);
+    bake_from_color::<G>(settings, color, samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::{Fern, FernSettings},
+        generator::BakeTarget,
+    };
+
+    /// Marks the sub-samples of every pixel covered by the triangles. The image is
+    /// sampled on a regular `samples`×`samples` grid per pixel.
+    fn coverage(triangles: &[[Vec2; 3]], width: u32, height: u32, samples: u32) -> Vec<u32> {
+        let mut mask = vec![0u32; (width * height) as usize];
+        cover(triangles, width, height, samples, |_, pixel, bits| {
+            mask[pixel] |= bits;
+        });
+        mask
+    }
+
+    fn fern_settings() -> FernSettings {
+        FernSettings {
+            target: BakeTarget::new(128, 32),
+            ..default()
+        }
+    }
+
+    /// The texel of `img` at `uv`.
+    fn texel(img: &Image, uv: Vec2) -> [u8; 4] {
+        let x = (uv.x * img.width() as f32) as usize;
+        let y = (uv.y * img.height() as f32) as usize;
+        let i = (y * img.width() as usize + x) * 4;
+        img.data[i..i + 4].try_into().unwrap()
+    }
+
+    /// Two triangles covering the rectangle from `min` to `max`.
+    fn rect(min: Vec2, max: Vec2) -> Vec<[Vec2; 3]> {
+        let (a, b) = (Vec2::new(max.x, min.y), Vec2::new(min.x, max.y));
+        vec![[min, a, max], [min, max, b]]
+    }
+
+    #[test]
+    fn coverage_of_half_a_pixel() {
+        // the left half of the left column of a 2×2 image
+        let mask = coverage(&rect(Vec2::new(-1.0, -1.0), Vec2::new(-0.5, 1.0)), 2, 2, 4);
+        let counts: Vec<u32> = mask.iter().map(|bits| bits.count_ones()).collect();
+        assert_eq!(counts, [8, 0, 8, 0]);
+    }
+
+    #[test]
+    fn coverage_ignores_the_winding() {
+        let triangle = [
+            Vec2::new(-2.0, -1.5),
+            Vec2::new(1.5, 0.5),
+            Vec2::new(-0.5, 2.0),
+        ];
+        let [a, b, c] = triangle;
+        let ccw = coverage(&[triangle], 4, 4, 3);
+        assert!(ccw.iter().any(|bits| *bits != 0));
+        assert_eq!(ccw, coverage(&[[a, c, b]], 4, 4, 3));
+    }
+
+    #[test]
+    fn coverage_is_clipped_to_the_image() {
+        let mask = coverage(&rect(Vec2::splat(-10.0), Vec2::new(10.0, 0.0)), 4, 4, 2);
+        // the lower half is covered completely, the upper half not at all
+        assert_eq!(
+            mask,
+            [0, 0, 0, 0, 0, 0, 0, 0, 15, 15, 15, 15, 15, 15, 15, 15]
+        );
+    }
+
+    #[test]
+    fn rasterized_fern() {
+        let settings = FernSettings {
+            target: BakeTarget::new(512, 128),
+            ..default()
+        };
+        let colors = [Color::RED, Color::GREEN, Color::BLUE, Color::WHITE];
+        let img = rasterize::<Fern>(&settings, &colors, 4);
+        let frame = Fern::frame(&settings);
+        let at = |x: f32, y: f32| texel(&img, frame.world_to_uv(Vec2::new(x, y)));
+
+        // the stem on top of the leaflets
+        assert_eq!(at(0.5, 0.0), [255, 0, 0, 255]);
+        assert_eq!(at(0.2, 0.01), [255, 0, 0, 255]);
+        // leaflets on both sides and the gap between them and the stem
+        assert_eq!(at(0.2, 0.2), [0, 0, 255, 255]);
+        assert_eq!(at(0.5, -0.1), [0, 255, 0, 255]);
+        assert_eq!(at(0.5, 0.1), [0, 0, 0, 0]);
+        assert_eq!(texel(&img, Vec2::ZERO), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn missing_palettes_are_white() {
+        let settings = FernSettings {
+            target: BakeTarget::new(512, 128),
+            ..default()
+        };
+        let img = rasterize::<Fern>(&settings, &[Color::RED], 2);
+        let frame = Fern::frame(&settings);
+        assert_eq!(
+            texel(&img, frame.world_to_uv(Vec2::new(0.5, 0.0))),
+            [255, 0, 0, 255]
+        );
+        assert!(img.data.chunks_exact(4).any(|p| p == [255, 255, 255, 255]));
+    }
+
+    fn map<'a>(maps: &'a [(&str, Image)], name: &str) -> &'a Image {
+        &maps.iter().find(|(map, _)| *map == name).unwrap().1
+    }
+
+    #[test]
+    fn normal_map_is_derived_from_the_height_pass() {
+        let settings = fern_settings();
+        let color = rasterize_palettes::<Fern>(&settings, &Fern::palettes(), 2);
+        let maps = bake_from_color::<Fern>(&settings, color.clone(), 2);
+        let normal = map(&maps, "normal");
+
+        let height = rasterize::<Fern>(&settings, &Fern::height_palette(), 2);
+        assert_eq!(map(&maps, "height").data, height.data);
+        let from_height = Fern::normal_map(&settings, &height).unwrap();
+        assert_eq!(normal.data, from_height.data);
+        let from_color = Fern::normal_map(&settings, &color).unwrap();
+        assert_ne!(normal.data, from_color.data);
+        // the thickness and ambient occlusion come from the same height map
+        let thickness = Fern::thickness_map(&settings, &height).unwrap();
+        assert_eq!(map(&maps, "thickness").data, thickness.data);
+    }
+}
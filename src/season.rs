@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+/// The appearance of a plant at a point of its year and life.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeasonState {
+    /// The colour the plant shifts towards.
+    pub color: Color,
+    /// How far the colour has shifted towards `color`.
+    pub tint: f32,
+    /// How much the fronds hang down.
+    pub droop: f32,
+    /// How much the leaflets roll up.
+    pub curl: f32,
+    /// Fraction of the leaflets that have fallen off.
+    pub loss: f32,
+}
+
+/// The keyframes of a year starting in spring.
+const YEAR: [SeasonState; 4] = [
+    // spring: fresh green, the fiddleheads are still unrolling
+    SeasonState {
+        color: Color::rgb(0.2, 0.5, 0.08),
+        tint: 0.35,
+        droop: 0.0,
+        curl: 0.4,
+        loss: 0.0,
+    },
+    // summer
+    SeasonState {
+        color: Color::rgb(0.05, 0.3, 0.0),
+        tint: 0.0,
+        droop: 0.1,
+        curl: 0.0,
+        loss: 0.0,
+    },
+    // autumn: rust
+    SeasonState {
+        color: Color::rgb(0.45, 0.2, 0.04),
+        tint: 0.7,
+        droop: 0.4,
+        curl: 0.2,
+        loss: 0.1,
+    },
+    // winter: dead and brown
+    SeasonState {
+        color: Color::rgb(0.25, 0.15, 0.07),
+        tint: 1.0,
+        droop: 0.8,
+        curl: 0.5,
+        loss: 0.4,
+    },
+];
+
+/// Interpolates the appearance for the `season` (0 spring, 0.25 summer, 0.5
+/// autumn, 0.75 winter, wrapping around) and the `age` in `[0, 1]` of the plant.
+/// Old plants droop more and lose more leaflets.
+pub fn season_state(season: f32, age: f32) -> SeasonState {
+    let t = season.rem_euclid(1.0) * YEAR.len() as f32;
+    let i = t.floor() as usize % YEAR.len();
+    let (a, b) = (YEAR[i], YEAR[(i + 1) % YEAR.len()]);
+    let f = t.fract();
+    let mix = |x: f32, y: f32| x + (y - x) * f;
+
+    let ca = Vec4::from(a.color.as_linear_rgba_f32());
+    let cb = Vec4::from(b.color.as_linear_rgba_f32());
+    let c = ca.lerp(cb, f);
+
+    let age = age.clamp(0.0, 1.0);
+    SeasonState {
+        color: Color::rgba_linear(c.x, c.y, c.z, c.w),
+        tint: mix(a.tint, b.tint) + (1.0 - mix(a.tint, b.tint)) * age.powi(3) * 0.5,
+        droop: (mix(a.droop, b.droop) + age * 0.3).min(1.0),
+        curl: mix(a.curl, b.curl),
+        loss: (mix(a.loss, b.loss) + age * age * 0.3).min(1.0),
+    }
+}
@@ -0,0 +1,52 @@
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+/// Library of leaf outlines shared by the generators.
+#[derive(Debug, Clone, Copy, Default, Reflect, PartialEq)]
+pub enum LeafShape {
+    /// Narrow and pointed, widest below the middle.
+    #[default]
+    Lanceolate,
+    /// Egg-shaped, widest close to the base.
+    Ovate,
+    /// Egg-shaped, widest close to the tip.
+    Obovate,
+    /// Widest in the middle with rounded ends.
+    Elliptic,
+    /// Heart-shaped with a broad base.
+    Cordate,
+    /// Long strap with parallel margins.
+    Linear,
+}
+
+impl LeafShape {
+    /// Half width of the leaf at `t` in `[0, 1]` from base to tip. The maximum is close to 1.
+    pub fn half_width(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            LeafShape::Lanceolate => (PI * t.powf(0.7)).sin(),
+            LeafShape::Ovate => (PI * t.powf(0.55)).sin(),
+            LeafShape::Obovate => (PI * t.powf(1.6)).sin(),
+            LeafShape::Elliptic => (PI * t).sin().sqrt(),
+            LeafShape::Cordate => (PI * (0.2 + 0.8 * t.powf(0.8))).sin().powf(0.7),
+            LeafShape::Linear => (t * 20.0).min(1.0) * ((1.0 - t) * 6.0).min(1.0),
+        }
+    }
+
+    /// Outline of a leaf starting at `start` pointing in direction `d` with the given
+    /// length and full width.
+    pub fn outline(&self, start: Vec2, d: Vec2, len: f32, w: f32, segments: usize) -> Vec<Vec2> {
+        let n = Vec2::new(-d.y, d.x);
+        let mut left = Vec::with_capacity(segments + 1);
+        let mut right = Vec::with_capacity(segments + 1);
+        for k in 0..=segments {
+            let t = k as f32 / segments as f32;
+            let half = w * 0.5 * self.half_width(t);
+            left.push(start + d * t * len + n * half);
+            right.push(start + d * t * len - n * half);
+        }
+        right.reverse();
+        left.append(&mut right);
+        left
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+use bevy_procedural_meshes::{lyon::PFill, *};
+use std::f32::consts::PI;
+
+use crate::{
+    components::{make_card_material, make_stalk_mesh, Stalk, StalkSettings},
+    draw::{hash, polygon},
+    generator::VegetationGenerator,
+    impostor::{unlit_capture, ImpostorCapture},
+};
+
+#[derive(Debug, Reflect, Component, PartialEq)]
+pub enum StalkPart {
+    Culm,
+    Node,
+    Sheath,
+    Leaf,
+}
+
+/// Position of the `k`-th node along the culm in `[start, 1]`.
+fn node_position(settings: &StalkSettings, k: u32) -> f32 {
+    let start = 0.02;
+    let prog = k as f32 / settings.nodes.max(1) as f32;
+    start + (1.0 - start) * prog.powf(settings.node_spacing)
+}
+
+/// Half width of the culm at `x`.
+fn culm_width(settings: &StalkSettings, x: f32) -> f32 {
+    settings.culm_w * (1.0 - settings.taper * x)
+}
+
+pub fn stalk_mesh(settings: &StalkSettings, part: StalkPart) -> PMesh<u16> {
+    let mut fill = PFill::new(0.0001);
+    fill.draw(|builder| {
+        let nodes = settings.nodes;
+
+        for k in 0..nodes {
+            let x0 = node_position(settings, k);
+            let x1 = node_position(settings, k + 1);
+            let w0 = culm_width(settings, x0);
+            let w1 = culm_width(settings, x1);
+            let dir = ((k % 2) * 2) as f32 - 1.0;
+
+            if part == StalkPart::Culm {
+                polygon(
+                    builder,
+                    &[
+                        Vec2::new(x0, w0),
+                        Vec2::new(x1, w1),
+                        Vec2::new(x1, -w1),
+                        Vec2::new(x0, -w0),
+                    ],
+                );
+            }
+
+            if part == StalkPart::Node {
+                // a slightly swollen ring around the node
+                let sw = w0 * (1.0 + settings.node_swelling);
+                let l = w0 * 0.4;
+                polygon(
+                    builder,
+                    &[
+                        Vec2::new(x0 - l, w0),
+                        Vec2::new(x0, sw),
+                        Vec2::new(x0 + l, w0),
+                        Vec2::new(x0 + l, -w0),
+                        Vec2::new(x0, -sw),
+                        Vec2::new(x0 - l, -w0),
+                    ],
+                );
+            }
+
+            if part == StalkPart::Sheath && settings.sheath_len > 0.0 {
+                // the sheath wraps the internode above the node and opens to alternating sides
+                let l = settings.sheath_len * (x1 - x0);
+                let ws = culm_width(settings, x0 + l);
+                polygon(
+                    builder,
+                    &[
+                        Vec2::new(x0, dir * w0 * 1.15),
+                        Vec2::new(x0 + l, dir * ws * 1.05),
+                        Vec2::new(x0 + l * 1.15, -dir * ws * 0.2),
+                        Vec2::new(x0, -dir * w0 * 1.15),
+                    ],
+                );
+            }
+
+            if part == StalkPart::Leaf && k >= settings.leaf_start && settings.leaves > 0 {
+                // a spray of leaves fanning out from the node
+                let leaves = settings.leaves;
+                let prog = k as f32 / nodes as f32;
+                for j in 0..leaves {
+                    let spread = if leaves > 1 {
+                        0.6 + 0.8 * j as f32 / (leaves - 1) as f32
+                    } else {
+                        1.0
+                    };
+                    let jitter = 0.2 * (hash(k, j) - 0.5);
+                    let a = dir * (settings.leaf_angle * spread + jitter);
+                    let d = Vec2::new(a.cos(), a.sin());
+                    let len = settings.leaf_len * (1.0 - 0.3 * prog) * (0.8 + 0.4 * hash(j, k));
+                    polygon(
+                        builder,
+                        &settings.leaf_shape.outline(
+                            Vec2::new(x0, dir * w0),
+                            d,
+                            len,
+                            settings.leaf_w,
+                            12,
+                        ),
+                    );
+                }
+            }
+        }
+    });
+    fill.build()
+}
+
+/// Places the stalks of a clump by letting rhizomes creep outwards from the
+/// origin, occasionally branching off into new runners. Returns one transform
+/// per stalk; the stalk grows along the local y-axis.
+pub fn rhizome_clump(settings: &StalkSettings) -> Vec<Transform> {
+    let seed = settings.seed;
+    let count = settings.clump_stalks;
+    let mut transforms = Vec::with_capacity(count as usize);
+
+    // (position, heading, age) of each growing runner
+    let mut runners = vec![(Vec2::ZERO, 0.0, 0u32)];
+    let mut i = 0;
+    while (transforms.len() as u32) < count {
+        let r = i as usize % runners.len();
+        let (pos, heading, age) = runners[r];
+
+        let height = 1.0 - 0.5 * (age as f32 / count.max(1) as f32) + 0.2 * hash(seed, 7 * i);
+        let lean = 0.1 * hash(seed, 7 * i + 1) * pos.normalize_or_zero();
+        transforms.push(
+            Transform::from_xyz(pos.x, 0.0, pos.y)
+                .with_rotation(
+                    Quat::from_rotation_z(-lean.x)
+                        * Quat::from_rotation_x(lean.y)
+                        * Quat::from_rotation_y(hash(seed, 7 * i + 2) * 2.0 * PI),
+                )
+                .with_scale(Vec3::splat(height)),
+        );
+
+        let turn = (hash(seed, 7 * i + 3) - 0.5) * 0.8;
+        let step = settings.rhizome_step * (0.7 + 0.6 * hash(seed, 7 * i + 4));
+        let heading = heading + turn;
+        let next = pos + Vec2::new(heading.cos(), heading.sin()) * step;
+        runners[r] = (next, heading, age + 1);
+
+        if hash(seed, 7 * i + 5) < settings.rhizome_branching {
+            let side = if hash(seed, 7 * i + 6) < 0.5 {
+                -1.0
+            } else {
+                1.0
+            };
+            runners.push((next, heading + side * PI / 3.0, age + 1));
+        }
+        i += 1;
+    }
+
+    transforms
+}
+
+impl VegetationGenerator for Stalk {
+    type Settings = StalkSettings;
+    type Part = StalkPart;
+    type Material = StandardMaterial;
+    const NAME: &'static str = "stalk";
+    const VERSION: u32 = 1;
+
+    fn parts() -> Vec<(StalkPart, f32)> {
+        // nodes and sheaths on top of the culm, leaves behind it
+        vec![
+            (StalkPart::Culm, 0.0),
+            (StalkPart::Node, 1.0),
+            (StalkPart::Sheath, 2.0),
+            (StalkPart::Leaf, -1.0),
+        ]
+    }
+
+    fn mesh(settings: &StalkSettings, part: StalkPart) -> PMesh<u16> {
+        stalk_mesh(settings, part)
+    }
+
+    fn palette() -> Vec<Color> {
+        vec![
+            Color::rgb(0.3, 0.4, 0.1),
+            Color::rgb(0.25, 0.3, 0.1),
+            Color::rgb(0.45, 0.4, 0.2),
+            Color::rgb(0.1, 0.3, 0.05),
+        ]
+    }
+
+    fn macro_mesh(_settings: &StalkSettings) -> Mesh {
+        make_stalk_mesh()
+    }
+
+    fn material(color: Option<Handle<Image>>, normal: Option<Handle<Image>>) -> Self::Material {
+        make_card_material(color, normal)
+    }
+
+    fn textures(
+        material: &mut Self::Material,
+    ) -> (&mut Option<Handle<Image>>, &mut Option<Handle<Image>>) {
+        (
+            &mut material.base_color_texture,
+            &mut material.normal_map_texture,
+        )
+    }
+
+    fn capture_material(
+        material: &Self::Material,
+        capture: ImpostorCapture,
+    ) -> Option<Self::Material> {
+        unlit_capture(material, capture)
+    }
+
+    fn instances(settings: &StalkSettings) -> Vec<Transform> {
+        rhizome_clump(settings)
+    }
+}
@@ -0,0 +1,36 @@
+//! Image fixtures shared by the tests.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+/// An 8 bit RGBA image of `size` with the texel at `(x, y)` given by `texel`.
+pub(crate) fn image(
+    size: UVec2,
+    format: TextureFormat,
+    texel: impl Fn(u32, u32) -> [u8; 4],
+) -> Image {
+    let data = (0..size.x * size.y)
+        .flat_map(|i| texel(i % size.x, i / size.x))
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// An 8 bit RGBA image of `size` filled with `texel`.
+pub(crate) fn filled(size: UVec2, format: TextureFormat, texel: [u8; 4]) -> Image {
+    image(size, format, |_, _| texel)
+}